@@ -1,11 +1,27 @@
 /// Core.rs
 /// このモジュールではrashinの基本的な構造体の定義と, イベントハンドラの定義を行う
+use std::cell::RefCell;
+use std::io::Cursor;
 use std::os::fd::RawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::error::RashinErr;
-use crate::http::http_interface::{Field, HTTPHeader, ParseResult};
-use crate::http::parse_request_header::{parse_http_request_header, process_reserved_header};
-use crate::http::parse_request_line::{ parse_http_request_line, RequestLineState};
+use crate::http::body::{BodyReader, BodyResult};
+use crate::http::config::ParserConfig;
+use crate::http::date::DateCache;
+use crate::http::header_value::Expectation;
+use crate::http::http_interface::{Field, HTTPHeader, HttpVersion, Method, ParseResult};
+use crate::http::multipart::{MultipartEvent, MultipartParser, MultipartResult};
+use crate::http::parse_request_header::{
+    parse_http_request_header, process_reserved_header, replace_obs_fold, validate_framing,
+    RequestHeaderState,
+};
+use crate::http::parse_request_line::{parse_http_request_line, RequestLineState};
+use crate::http::request::Request;
+use crate::http::response::{BodyWriter, Response, ResponseContext, TransferMode};
+use crate::http::status::StatusCode;
+use crate::http::uri::PathMode;
 use crate::syscall;
 
 #[derive(Clone, Debug)]
@@ -32,19 +48,480 @@ impl Event {
     }
 }
 
+/// サーバー全体の設定
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub parser: ParserConfig,
+    /// リクエストボディの最大長。超えた場合は413 Content Too Largeを返す。Noneの場合は制限しない
+    pub client_max_body_size: Option<u64>,
+    /// pathごとの設定。最も長く一致したものを使う
+    pub locations: Vec<Location>,
+    /// レスポンスのServerヘッダーの値。Noneの場合は送信しない
+    pub server_token: Option<String>,
+    /// 次のリクエストを待つ間, 接続を維持する時間。
+    /// リクエストの途中でもこの時間データが届かなければ接続を閉じる
+    pub keep_alive_timeout: Duration,
+    /// リクエストの最初のバイトを受信してから, ヘッダーセクションを受信し終えるまでの時間。
+    /// 少しずつ送り続けて接続を占有するクライアントを切断する
+    pub client_header_timeout: Duration,
+    /// 1つの接続で処理するリクエストの最大数。超えた場合は接続を閉じる
+    pub keep_alive_requests: usize,
+    /// リクエストに応答するハンドラー
+    pub handler: Handler,
+}
+
+/// リクエストに応答するハンドラー。
+/// ヘッダーを読み終えた時に呼び出され, ボディはread_bodyかbuffer_bodyで読む。
+/// ボディの続きが必要な場合はNoneを返すと, 続きを読み込んだ後に再び呼び出される。
+/// ボディを読み終える前に応答した場合は, レスポンスを送信した後に接続を閉じる。
+pub type Handler = fn(&mut Connection) -> Option<Response>;
+
+impl ServerConfig {
+    /// 正規化したpathに対応するリクエストボディの最大長
+    pub fn client_max_body_size(&self, path: &[u8]) -> Option<u64> {
+        self.location(path)
+            .and_then(|location| location.client_max_body_size)
+            .unwrap_or(self.client_max_body_size)
+    }
+
+    /// pathに前方一致するLocationのうち, 最も長いものを返す
+    pub fn location(&self, path: &[u8]) -> Option<&Location> {
+        self.locations
+            .iter()
+            .filter(|location| path.starts_with(location.prefix.as_bytes()))
+            .max_by_key(|location| location.prefix.len())
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            parser: ParserConfig::default(),
+            client_max_body_size: Some(1024 * 1024),
+            locations: Vec::new(),
+            server_token: Some("rashin".to_string()),
+            keep_alive_timeout: Duration::from_secs(75),
+            client_header_timeout: Duration::from_secs(60),
+            keep_alive_requests: 1000,
+            handler: handle_request,
+        }
+    }
+}
+
+/// pathの前方一致で選ばれる設定
+#[derive(Clone, Debug, Default)]
+pub struct Location {
+    pub prefix: String,
+    /// ServerConfig::client_max_body_sizeを上書きする。Some(None)の場合は制限しない
+    pub client_max_body_size: Option<Option<u64>>,
+}
+
+/// リクエストをどこまでパースしたか
+#[derive(Clone, Debug)]
+pub enum RequestPhase {
+    RequestLine(RequestLineState),
+    Header(RequestHeaderState),
+    Complete,
+    /// ヘッダーを読み終え, ボディを読んでいる
+    Body,
+}
+
+thread_local! {
+    /// 全ての接続で共有するDateヘッダーの値
+    static DATE_CACHE: RefCell<DateCache> = RefCell::new(DateCache::new());
+}
+
+/// fill_bufferで読み込みを止めた理由
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadStatus {
+    /// EAGAINになったので, 次のEPOLLINを待つ
+    WouldBlock,
+    /// bufが一杯になった
+    Full,
+    /// 相手が接続を閉じた
+    Closed,
+}
+
+/// flushで書き込みを止めた理由
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStatus {
+    /// 送信待ちのデータを全て書き込んだ
+    Done,
+    /// EAGAINになったので, 次のEPOLLOUTを待つ
+    WouldBlock,
+    /// 相手が接続を閉じた
+    Closed,
+}
+
 #[derive(Clone, Debug)]
 pub struct Connection {
     pub fd: RawFd,
     pub buf: Vec<u8>,
+    pub config: Arc<ServerConfig>,
+    /// bufに読み込み済みのバイト数
+    pub filled: usize,
+    /// bufのうちパースが済んだ位置
+    pub parsed: usize,
+    pub phase: RequestPhase,
+    pub header: HTTPHeader,
+    /// パース途中のヘッダー
+    pub field: Field,
+    /// ボディの読み込み状態。ヘッダーを読み終えるまではNone
+    pub body: Option<BodyReader>,
+    /// bufのうちボディが始まる位置
+    pub body_start: usize,
+    /// buffer_bodyで読み込んだボディ
+    pub body_buf: Vec<u8>,
+    /// multipart/form-dataのボディの読み込み状態
+    pub multipart: Option<MultipartParser>,
+    /// 100 Continueを送信済みかどうか
+    pub continue_sent: bool,
+    /// read_bodyかbuffer_bodyがTooLargeを返したかどうか。
+    /// ハンドラーのレスポンスに関わらず413 Content Too Largeを返す
+    pub body_too_large: bool,
+    /// 送信待ちのレスポンス
+    pub out_buf: Vec<u8>,
+    /// ストリーミングしているレスポンスボディの書き込み状態
+    pub body_writer: Option<BodyWriter>,
+    /// out_bufを送信し終えたら接続を閉じる
+    pub closing: bool,
+    /// この接続で応答したリクエストの数
+    pub requests: usize,
+    /// 最後にデータを送受信した時刻
+    pub last_active: Instant,
+    /// 読み込み中のリクエストの最初のバイトを受信した時刻
+    pub request_start: Instant,
 }
 
 impl Connection {
-    pub fn new(fd: RawFd) -> Connection {
+    /// リクエストラインとヘッダーセクションが上限まで収まるバッファを確保する
+    pub fn new(fd: RawFd, config: Arc<ServerConfig>) -> Connection {
         Connection {
-            fd: fd,
-            buf: vec![0 as u8; 1024],
+            fd,
+            buf: vec![0_u8; config.parser.buffer_size()],
+            config,
+            filled: 0,
+            parsed: 0,
+            phase: RequestPhase::RequestLine(RequestLineState::Start),
+            header: HTTPHeader::new(),
+            field: Field::new(),
+            body: None,
+            body_start: 0,
+            body_buf: Vec::new(),
+            multipart: None,
+            continue_sent: false,
+            body_too_large: false,
+            out_buf: Vec::new(),
+            body_writer: None,
+            closing: false,
+            requests: 0,
+            last_active: Instant::now(),
+            request_start: Instant::now(),
         }
     }
+
+    /// ソケットからbufの空きに読み込む。
+    /// Edge Triggerなので, EAGAINになるかbufが一杯になるまで読み込む。
+    /// ボディを読んでいる間は, 読み終えた部分を詰めてから読み込む。
+    pub fn fill_buffer(&mut self) -> ReadStatus {
+        self.compact_body();
+        while self.filled < self.buf.len() {
+            match syscall::read(self.fd, &mut self.buf[self.filled..]) {
+                Ok(0) => return ReadStatus::Closed,
+                Ok(size) => {
+                    self.last_active = Instant::now();
+                    if self.filled == 0 {
+                        self.request_start = self.last_active;
+                    }
+                    self.filled += size as usize;
+                }
+                Err(RashinErr::SyscallError(libc::EAGAIN)) => return ReadStatus::WouldBlock,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return ReadStatus::Closed;
+                }
+            }
+        }
+        ReadStatus::Full
+    }
+
+    /// 読み込み済みのデータを前回中断した位置からパースする。
+    /// データが足りない場合はAgainを返すので, 追加のデータを読み込んでから再度呼び出す。
+    pub fn parse_request(&mut self) -> ParseResult<RequestPhase> {
+        let parser_config = &self.config.parser;
+        let buf = &self.buf[..self.filled];
+        let mut cursor = Cursor::new(buf);
+        cursor.set_position(self.parsed as u64);
+
+        let result = loop {
+            let result = match self.phase.clone() {
+                RequestPhase::RequestLine(state) => {
+                    match parse_http_request_line(
+                        &mut cursor,
+                        &mut self.header,
+                        state,
+                        parser_config,
+                    ) {
+                        ParseResult::Complete => {
+                            ParseResult::Ok(RequestPhase::Header(RequestHeaderState::Start))
+                        }
+                        ParseResult::Again(state) => {
+                            ParseResult::Again(RequestPhase::RequestLine(state))
+                        }
+                        ParseResult::TooLarge => ParseResult::TooLarge,
+                        _ => ParseResult::Error,
+                    }
+                }
+                RequestPhase::Header(state) => {
+                    match parse_http_request_header(
+                        &mut cursor,
+                        &mut self.header,
+                        &mut self.field,
+                        state,
+                        parser_config,
+                    ) {
+                        ParseResult::Complete if self.field.is_separator => ParseResult::Complete,
+                        ParseResult::Complete => {
+                            self.field = Field::new();
+                            ParseResult::Ok(RequestPhase::Header(RequestHeaderState::Start))
+                        }
+                        ParseResult::Again(state) => {
+                            ParseResult::Again(RequestPhase::Header(state))
+                        }
+                        ParseResult::TooLarge => ParseResult::TooLarge,
+                        _ => ParseResult::Error,
+                    }
+                }
+                RequestPhase::Complete | RequestPhase::Body => ParseResult::Complete,
+            };
+
+            self.parsed = cursor.position() as usize;
+            match result {
+                ParseResult::Ok(next_phase) => self.phase = next_phase,
+                ParseResult::Again(phase) => {
+                    self.phase = phase.clone();
+                    return ParseResult::Again(phase);
+                }
+                _ => break result,
+            }
+        };
+        match (result, &self.phase) {
+            (ParseResult::Complete, RequestPhase::Header(_)) => self.process_fields(),
+            (result, _) => result,
+        }
+    }
+
+    /// ヘッダーセクションを読み終えた後に, サーバーが解釈するヘッダーを処理する。
+    /// obs-foldで値が後から延びることがあるので, 全てのヘッダーが揃ってから処理する。
+    fn process_fields(&mut self) -> ParseResult<RequestPhase> {
+        replace_obs_fold(&self.header, &mut self.buf[..self.filled]);
+        let buf = &self.buf[..self.filled];
+        for i in 0..self.header.fields.len() {
+            let field = self.header.fields[i];
+            if let Err(e) = process_reserved_header(&mut self.header, &field, &buf) {
+                log::debug!("{}", e);
+                return ParseResult::Error;
+            }
+        }
+        if let Err(e) = validate_framing(&self.header) {
+            log::debug!("{}", e);
+            return ParseResult::Error;
+        }
+        self.phase = RequestPhase::Complete;
+        ParseResult::Complete
+    }
+
+    /// パースしたリクエストラインとヘッダーを参照する
+    pub fn request(&self) -> Request<'_> {
+        Request::new(&self.header, &self.buf[..self.filled])
+    }
+
+    /// ヘッダーを読み終えた後に呼び出し, ボディの読み込みを始める。
+    /// ボディがlimitを超えるとread_bodyはTooLargeを返す。
+    pub fn start_body(&mut self, limit: Option<u64>) {
+        self.body = Some(BodyReader::new(&self.header, limit));
+        self.body_start = self.parsed;
+        self.multipart = MultipartParser::from_header(&self.header, &self.buf);
+        self.phase = RequestPhase::Body;
+    }
+
+    /// 読み込み済みのデータからボディを読み進める。
+    /// Content-Lengthかchunkedかに関わらず, ボディはself.bufの範囲としてData(range)で返す。
+    /// 範囲は次にfill_bufferを呼び出すまで有効である。
+    ///
+    /// Expect: 100-continueが指定されている場合は, 最初に呼び出した時に100 Continueを送信する。
+    /// ボディを読まずに応答する場合は100 Continueを送信しない。
+    pub fn read_body(&mut self) -> BodyResult {
+        let is_complete = match &self.body {
+            Some(reader) => reader.is_complete(),
+            None => return BodyResult::Error,
+        };
+        if !self.continue_sent && self.header.expect == Some(Expectation::Continue) {
+            self.continue_sent = true;
+            // HTTP/1.0のクライアントには送信せず, ボディが届き始めている場合も省略する
+            if self.header.version >= HttpVersion::HTTP_1_1
+                && !is_complete
+                && self.parsed == self.filled
+            {
+                // クライアントは100 Continueを待っているので, すぐに送信する
+                self.send(&Response::new(StatusCode::CONTINUE), true);
+                self.flush();
+            }
+        }
+        let reader = match self.body.as_mut() {
+            Some(reader) => reader,
+            None => return BodyResult::Error,
+        };
+        let mut cursor = Cursor::new(&self.buf[..self.filled]);
+        cursor.set_position(self.parsed as u64);
+        let result = reader.read(&mut cursor, &self.config.parser);
+        self.parsed = cursor.position() as usize;
+        if let BodyResult::TooLarge = result {
+            self.body_too_large = true;
+        }
+        result
+    }
+
+    /// ボディ全体をbody_bufに読み込む。limitを超える場合はTooLargeを返す。
+    /// 入力が足りない場合はAgainを返すので, 追加のデータを読み込んでから再度呼び出す。
+    pub fn buffer_body(&mut self, limit: usize) -> BodyResult {
+        loop {
+            match self.read_body() {
+                BodyResult::Data(range) => {
+                    if self.body_buf.len() + range.len() > limit {
+                        self.body_too_large = true;
+                        return BodyResult::TooLarge;
+                    }
+                    self.body_buf.extend_from_slice(&self.buf[range]);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// レスポンスを送信待ちのバッファに書き込む。ソケットへの書き込みはflushで行う。
+    /// keep_aliveはレスポンスの送信後に接続を維持するかどうかで, Connectionヘッダーに反映する。
+    /// 接続を閉じてボディの終わりを示す場合があるので, 実際に接続を維持できるかどうかを返す。
+    ///
+    /// ストリーミングするボディはsend_bodyで書き込み, finish_bodyで終える。
+    pub fn send(&mut self, response: &Response, keep_alive: bool) -> bool {
+        log::debug!("Send: {} {}", response.status, response.reason);
+        let date = DATE_CACHE.with(|cache| *cache.borrow_mut().now());
+        let context = ResponseContext {
+            date: Some(&date),
+            server: self.config.server_token.as_deref(),
+            version: self.header.version,
+            keep_alive,
+            head: matches!(self.header.method(&self.buf), Method::Head),
+        };
+        self.body_writer = response.serialize(&mut self.out_buf, &context);
+        let close_delimited = self
+            .body_writer
+            .as_ref()
+            .is_some_and(|writer| writer.mode() == TransferMode::CloseDelimited);
+        keep_alive && !close_delimited
+    }
+
+    /// ストリーミングしているボディの続きを送信待ちのバッファに書き込む
+    pub fn send_body(&mut self, data: &[u8]) {
+        if let Some(writer) = self.body_writer.as_mut() {
+            writer.write(data, &mut self.out_buf);
+        }
+    }
+
+    /// ストリーミングしているボディを終える。chunkedの場合はtrailersを送信する。
+    pub fn finish_body(&mut self, trailers: &[(&str, &[u8])]) -> Result<(), RashinErr> {
+        match self.body_writer.as_mut() {
+            Some(writer) => writer.finish(trailers, &mut self.out_buf),
+            None => Ok(()),
+        }
+    }
+
+    /// 送信待ちのデータをソケットに書き込む。
+    /// Edge Triggerなので, 全て書き込むかEAGAINになるまで書き込む。
+    /// 書き込めなかったデータはout_bufに残る。
+    pub fn flush(&mut self) -> WriteStatus {
+        let mut written = 0;
+        let status = loop {
+            if written == self.out_buf.len() {
+                break WriteStatus::Done;
+            }
+            match syscall::write(self.fd, &mut self.out_buf[written..]) {
+                Ok(sent) => {
+                    written += sent.len();
+                    self.last_active = Instant::now();
+                }
+                Err(RashinErr::SyscallError(libc::EAGAIN)) => break WriteStatus::WouldBlock,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    break WriteStatus::Closed;
+                }
+            }
+        };
+        self.out_buf.drain(..written);
+        status
+    }
+
+    /// 読み終えたボディを詰めて, 続きを読み込む場所を空けられるかどうか
+    fn can_compact_body(&self) -> bool {
+        self.parsed > self.body_start
+            && self
+                .body
+                .as_ref()
+                .is_some_and(|reader| reader.can_compact())
+    }
+
+    /// 読み終えたボディをbufから取り除き, 続きを読み込む場所を空ける
+    fn compact_body(&mut self) {
+        if !self.can_compact_body() {
+            return;
+        }
+        self.buf
+            .copy_within(self.parsed..self.filled, self.body_start);
+        self.filled -= self.parsed - self.body_start;
+        self.parsed = self.body_start;
+    }
+
+    /// 次のリクエストを待っていて, 読み込み途中のリクエストも送信待ちのレスポンスも無いかどうか
+    pub fn is_idle(&self) -> bool {
+        self.filled == 0 && self.out_buf.is_empty()
+    }
+
+    /// 接続を閉じるべき時間が過ぎたかどうか。
+    /// データを送受信できないままkeep_alive_timeoutを過ぎた場合と,
+    /// ヘッダーセクションを受信し終えないままclient_header_timeoutを過ぎた場合に閉じる。
+    /// レスポンスを読まないクライアントも, 送信が進まなければ閉じる。
+    pub fn is_expired(&self, now: Instant) -> bool {
+        let is_reading_header = self.filled > 0
+            && matches!(
+                self.phase,
+                RequestPhase::RequestLine(_) | RequestPhase::Header(_)
+            );
+        now.saturating_duration_since(self.last_active) >= self.config.keep_alive_timeout
+            || (is_reading_header
+                && now.saturating_duration_since(self.request_start)
+                    >= self.config.client_header_timeout)
+    }
+
+    /// 次のリクエストを受け付けられるようにパースの状態を初期化する。
+    /// パイプライン化された次のリクエストを既に読み込んでいる場合は, バッファの先頭に移して残す。
+    pub fn reset(&mut self) {
+        self.buf.copy_within(self.parsed..self.filled, 0);
+        self.filled -= self.parsed;
+        self.parsed = 0;
+        self.phase = RequestPhase::RequestLine(RequestLineState::Start);
+        self.header = HTTPHeader::new();
+        self.field = Field::new();
+        self.body = None;
+        self.body_start = 0;
+        self.body_buf.clear();
+        self.multipart = None;
+        self.continue_sent = false;
+        self.body_too_large = false;
+        self.body_writer = None;
+        // パイプライン化された次のリクエストは既に届き始めている
+        self.request_start = Instant::now();
+    }
 }
 
 pub fn init_http_event(connection: Connection) -> Event {
@@ -57,84 +534,825 @@ pub fn init_http_event(connection: Connection) -> Event {
     }
 }
 
+/// http_handlerの各段階の処理結果
+enum Progress {
+    /// 続けて処理する
+    Continue,
+    /// 次のEPOLLINを待つ
+    Again,
+    /// 接続を閉じる
+    Shutdown,
+}
+
 pub fn http_handler(fd: RawFd, event: &mut Event) {
     if !event.is_ready() {
         println!("Not ready");
         return;
     }
+    let connection = match &mut event.connection {
+        Some(connection) => connection,
+        None => {
+            println!("Connection is None.");
+            return;
+        }
+    };
+
+    // 前回送信しきれなかったレスポンスを先に送信する
+    if event.writable && !connection.out_buf.is_empty() {
+        match connection.flush() {
+            WriteStatus::Done if !connection.closing => {}
+            WriteStatus::WouldBlock => {
+                event.writable = false;
+                return;
+            }
+            WriteStatus::Done | WriteStatus::Closed => {
+                event.state = EventState::Shutdown;
+                return;
+            }
+        }
+    }
 
     if !(event.readable && event.writable) {
         println!("continue");
         return;
     }
     println!("Get ready to read from {}.", &fd);
-    if let Some(connection) = &mut event.connection {
-        let read_option = syscall::read(fd, &mut connection.buf);
-        let size = match read_option {
-            Ok(size) => size as usize,
-            Err(RashinErr::SyscallError(libc::EAGAIN)) => {
-                event.readable = false;
-                println!("EAGAIN");
+    loop {
+        let status = connection.fill_buffer();
+        if status == ReadStatus::WouldBlock {
+            event.readable = false;
+            println!("EAGAIN");
+        }
+
+        let progress = match connection.phase {
+            RequestPhase::Body => process_body(connection, status),
+            _ => match process_header(connection, status) {
+                Progress::Continue => process_body(connection, status),
+                progress => progress,
+            },
+        };
+        match (progress, connection.flush()) {
+            (Progress::Continue, WriteStatus::Done) => continue,
+            (Progress::Again, WriteStatus::Done) => return,
+            // 送信しきれなかった分は次のEPOLLOUTで送信する
+            (progress, WriteStatus::WouldBlock) => {
+                event.writable = false;
+                connection.closing = matches!(progress, Progress::Shutdown);
                 return;
             }
-            Err(e) => {
-                panic!("Error: {}", e);
+            (Progress::Shutdown, WriteStatus::Done) | (_, WriteStatus::Closed) => {
+                event.state = EventState::Shutdown;
+                return;
             }
-        };
+        }
+    }
+}
 
-        let mut header = HTTPHeader::new();
-        let mut cursor = std::io::Cursor::new(&connection.buf);
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
-        if let ParseResult::Complete = result {
-            log::debug!("Method: {}", header.method(&connection.buf));
-            println!("Path: {}", header.path(&connection.buf));
-            log::debug!("Protocol: {}", header.protocol(&connection.buf));
-        } else {
+/// リクエストラインとヘッダーをパースし, ボディを読む前に処理できないリクエストを拒否する
+fn process_header(connection: &mut Connection, status: ReadStatus) -> Progress {
+    let result = match connection.parse_request() {
+        ParseResult::Complete => check_request(&connection.request(), &connection.config),
+        ParseResult::Again(_) => match status {
+            // 相手が接続を閉じた
+            ReadStatus::Closed => return Progress::Shutdown,
+            ReadStatus::WouldBlock => return Progress::Again,
+            // バッファに収まらないリクエストは上限を超えている
+            ReadStatus::Full => Err(too_large_status(&connection.phase)),
+        },
+        ParseResult::TooLarge => Err(too_large_status(&connection.phase)),
+        _ => {
             println!("Parse Error");
+            Err(StatusCode::BAD_REQUEST)
+        }
+    };
+    match result {
+        Ok(limit) => {
+            connection.start_body(limit);
+            Progress::Continue
         }
+        Err(status) => {
+            connection.send(&Response::new(status), false);
+            Progress::Shutdown
+        }
+    }
+}
 
-        loop {
-            let mut field = Field::new();
-            let result = parse_http_request_header(&mut cursor, &mut field);
+/// リクエストラインとヘッダーが上限を超えた場合のステータスコード
+fn too_large_status(phase: &RequestPhase) -> StatusCode {
+    match phase {
+        RequestPhase::RequestLine(_) => StatusCode::URI_TOO_LONG,
+        _ => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+    }
+}
+
+/// ボディを読む前に応答できるリクエストかどうかを調べ, ボディの最大長を返す。
+/// 処理できない場合はレスポンスのステータスコードを返す。
+fn check_request(request: &Request, config: &ServerConfig) -> Result<Option<u64>, StatusCode> {
+    log::debug!("Method: {}", request.method());
+    println!("Path: {}", String::from_utf8_lossy(request.path()));
+    log::debug!("Version: {:?}", request.version());
+    if let Some(host) = request.host() {
+        log::debug!("Host: {}", String::from_utf8_lossy(&host));
+    }
 
-            if field.is_separator {
-                break;
+    // 対応していないバージョンのリクエストは, その他の規則を当てはめずに拒否する
+    let header = request.header();
+    if !header.version.is_supported() {
+        return Err(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+    }
+
+    // HTTP/1.1ではHostヘッダーが必須である
+    if header.version.major == 1 && header.version.minor >= 1 && header.host.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // ドキュメントルートの外を参照されないように, 正規化できないpathは拒否する
+    let path = request
+        .normalized_path(PathMode::Strict)
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    log::debug!("Normalized Path: {}", String::from_utf8_lossy(&path));
+
+    if !is_implemented_method(&request.method()) {
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+    // 100-continue以外の期待には応えられない
+    if header.expect == Some(Expectation::Unsupported) {
+        return Err(StatusCode::EXPECTATION_FAILED);
+    }
+    // chunked以外の転送コーディングはデコードできないので, ボディをそのまま渡さずに拒否する。
+    // chunkedが最後でない場合はvalidate_framingで400になっている
+    if header.transfer_encoding.len() > 1 {
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+
+    // Content-Lengthが上限を超える場合はボディを読まずに接続を閉じる
+    let limit = config.client_max_body_size(&path);
+    if let (Some(limit), Some(length)) = (limit, header.content_length) {
+        if length > limit {
+            return Err(StatusCode::CONTENT_TOO_LARGE);
+        }
+    }
+    Ok(limit)
+}
+
+/// ハンドラーを呼び出し, ハンドラーがレスポンスを返したら送信する
+fn process_body(connection: &mut Connection, status: ReadStatus) -> Progress {
+    let handler = connection.config.handler;
+    let response = handler(connection);
+    // ボディの上限はハンドラーに関わらずサーバーが守らせる
+    if connection.body_too_large {
+        connection.send(&Response::new(StatusCode::CONTENT_TOO_LARGE), false);
+        return Progress::Shutdown;
+    }
+    let response = match response {
+        Some(response) => response,
+        None => match status {
+            ReadStatus::Closed => return Progress::Shutdown,
+            ReadStatus::WouldBlock => return Progress::Again,
+            // 読み終えた部分を詰めれば続きを読み込める
+            ReadStatus::Full if connection.can_compact_body() => return Progress::Continue,
+            // ヘッダーでバッファが埋まり, ボディを読み込む場所が無い
+            ReadStatus::Full => {
+                let response = Response::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+                connection.send(&response, false);
+                return Progress::Shutdown;
             }
+        },
+    };
 
-            match result {
-                ParseResult::Complete => {
-                    process_reserved_header(&mut header, field.name(&connection.buf), field.value(&connection.buf));
-                }
-                ParseResult::Error => {
-                    println!("Parse Error");
-                    break;
-                }
-                ParseResult::Again(_) => {
-                    println!("Again");
-                    break;
-                }
-                _ => {
-                    println!("Complete");
-                    break;
+    // HTTP/1.0はkeep-aliveが要求された場合のみ, HTTP/1.1はcloseが要求されない限り接続を維持する。
+    // 1つの接続で処理するリクエストの数が上限に達した場合も閉じる。
+    // 読み残したボディを次のリクエストとして読まないように, ボディを読み終えていない場合も閉じる
+    connection.requests += 1;
+    let keep_alive = connection.header.is_keep_alive()
+        && connection.requests < connection.config.keep_alive_requests
+        && connection
+            .body
+            .as_ref()
+            .is_some_and(|body| body.is_complete());
+    let keep_alive = connection.send(&response, keep_alive);
+    // ハンドラーが返った後はボディを書き込めないので, ストリーミングのボディはここで終える。
+    // last-chunkを送らずに次のレスポンスを送ると, chunkの続きとして読まれてしまう
+    if let Err(e) = connection.finish_body(&[]) {
+        log::debug!("{}", e);
+    }
+    if !keep_alive {
+        return Progress::Shutdown;
+    }
+    connection.reset();
+    // 次のリクエストを読み込み済みであれば, 新たな入力を待たずに処理する
+    if connection.filled > 0 {
+        return Progress::Continue;
+    }
+    match status {
+        ReadStatus::Closed => Progress::Shutdown,
+        ReadStatus::WouldBlock => Progress::Again,
+        ReadStatus::Full => Progress::Continue,
+    }
+}
+
+/// デフォルトのハンドラー。
+/// ボディを読み捨て, multipart/form-dataの場合は各partをログに出力する
+fn handle_request(connection: &mut Connection) -> Option<Response> {
+    loop {
+        let status = match connection.read_body() {
+            BodyResult::Data(range) => {
+                log::debug!("Body: {} bytes", range.len());
+                match connection.multipart.as_mut().map(|parser| {
+                    parser.feed(
+                        &connection.buf[range],
+                        &connection.config.parser,
+                        &mut log_multipart_event,
+                    )
+                }) {
+                    Some(MultipartResult::TooLarge) => StatusCode::CONTENT_TOO_LARGE,
+                    Some(MultipartResult::Error) => StatusCode::BAD_REQUEST,
+                    _ => continue,
                 }
             }
+            BodyResult::Complete => break,
+            BodyResult::Again => return None,
+            BodyResult::TooLarge => StatusCode::CONTENT_TOO_LARGE,
+            BodyResult::Error => StatusCode::BAD_REQUEST,
+        };
+        return Some(Response::new(status));
+    }
+    if let Some(body) = &connection.body {
+        log::debug!("Received body: {} bytes", body.received());
+    }
+    // close-delimiterの前にボディが終わった
+    if connection
+        .multipart
+        .as_ref()
+        .is_some_and(|parser| !parser.is_complete())
+    {
+        return Some(Response::new(StatusCode::BAD_REQUEST));
+    }
+
+    let request = connection.request();
+    log::debug!(
+        "Handle: {} {}",
+        request.method(),
+        String::from_utf8_lossy(request.target())
+    );
+    Some(Response::new(StatusCode::NO_CONTENT))
+}
+
+fn log_multipart_event(event: MultipartEvent) {
+    match event {
+        MultipartEvent::PartStart(part) => log::debug!(
+            "Part: name={:?} filename={:?}",
+            part.name()
+                .map(|name| String::from_utf8_lossy(&name).into_owned()),
+            part.filename()
+                .map(|name| String::from_utf8_lossy(&name).into_owned())
+        ),
+        MultipartEvent::Data(data) => log::debug!("Part data: {} bytes", data.len()),
+        MultipartEvent::PartEnd => log::debug!("Part end"),
+    }
+}
+
+/// サーバーが処理できるメソッドかどうか。
+/// プロキシとして動作しないためCONNECTは扱わず、TRACEは情報漏洩を避けるため無効にしている。
+fn is_implemented_method(method: &Method) -> bool {
+    matches!(
+        method,
+        Method::Get
+            | Method::Head
+            | Method::Post
+            | Method::Put
+            | Method::Delete
+            | Method::Options
+            | Method::Patch
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::config::ObsFold;
+
+    #[test]
+    fn client_max_body_size_per_location() {
+        let config = ServerConfig {
+            client_max_body_size: Some(100),
+            locations: vec![
+                Location {
+                    prefix: "/upload".to_string(),
+                    client_max_body_size: Some(Some(1000)),
+                },
+                Location {
+                    prefix: "/upload/unlimited".to_string(),
+                    client_max_body_size: Some(None),
+                },
+                Location {
+                    prefix: "/static".to_string(),
+                    client_max_body_size: None,
+                },
+            ],
+            ..ServerConfig::default()
+        };
+        assert_eq!(config.client_max_body_size(b"/"), Some(100));
+        assert_eq!(config.client_max_body_size(b"/upload/file"), Some(1000));
+        assert_eq!(config.client_max_body_size(b"/upload/unlimited/file"), None);
+        assert_eq!(
+            config.client_max_body_size(b"/static/index.html"),
+            Some(100)
+        );
+    }
+
+    /// 接続されたソケットの組を作り, 片方をConnectionにする
+    fn connected_pair(config: ServerConfig) -> (Connection, RawFd) {
+        let mut fds = [0; 2];
+        let result =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+        assert_eq!(result, 0);
+        syscall::fnctl(fds[0]).unwrap();
+        (Connection::new(fds[0], Arc::new(config)), fds[1])
+    }
+
+    fn read_all(fd: RawFd) -> Vec<u8> {
+        let mut buf = vec![0; 4096];
+        let size = syscall::read(fd, &mut buf).unwrap();
+        buf.truncate(size as usize);
+        buf
+    }
+
+    #[test]
+    fn stream_response_body() {
+        let config = ServerConfig {
+            server_token: None,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        let mut response = Response::new(StatusCode::OK);
+        response.set_streaming();
+        assert!(connection.send(&response, true));
+        connection.send_body(b"hello");
+        connection.finish_body(&[("X-Sum", b"5")]).unwrap();
+        assert_eq!(connection.flush(), WriteStatus::Done);
+
+        let out = read_all(peer);
+        assert!(out.starts_with(b"HTTP/1.1 200 OK\r\nDate: "));
+        assert!(out.ends_with(
+            b"GMT\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nX-Sum: 5\r\n\r\n"
+        ));
+
+        // HTTP/1.0のクライアントには接続を閉じてボディの終わりを示す
+        connection.reset();
+        connection.header.version = HttpVersion::HTTP_1_0;
+        assert!(!connection.send(&response, true));
+        connection.send_body(b"hello");
+        connection.finish_body(&[]).unwrap();
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        let out = read_all(peer);
+        assert!(out.ends_with(b"GMT\r\nConnection: close\r\n\r\nhello"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    fn write_all(fd: RawFd, data: &[u8]) {
+        let mut data = data.to_vec();
+        assert_eq!(syscall::write(fd, &mut data).unwrap().len(), data.len());
+    }
+
+    /// 読み込み済みのデータからリクエストを1つ処理する
+    fn process(connection: &mut Connection, status: ReadStatus) -> Progress {
+        match process_header(connection, status) {
+            Progress::Continue => process_body(connection, status),
+            progress => progress,
         }
+    }
+
+    #[test]
+    fn obs_fold_is_replaced_in_buffer() {
+        let config = ServerConfig {
+            parser: ParserConfig {
+                obs_fold: ObsFold::Replace,
+                ..ParserConfig::default()
+            },
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(peer, b"GET / HTTP/1.1\r\nHost: a\r\nX-A: b\r\n c\r\n\r\n");
+        connection.fill_buffer();
+        assert!(matches!(connection.parse_request(), ParseResult::Complete));
+        // 値をそのまま送り返してもCR/LFが含まれない
+        assert_eq!(connection.request().get("X-A"), Some(&b"b   c"[..]));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn unsupported_transfer_coding_is_not_implemented() {
+        let (mut connection, peer) = connected_pair(ServerConfig::default());
+        write_all(
+            peer,
+            b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+        );
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Shutdown
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        assert!(read_all(peer).starts_with(b"HTTP/1.1 501 Not Implemented\r\n"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn unsupported_version_without_host() {
+        let (mut connection, peer) = connected_pair(ServerConfig::default());
+        write_all(peer, b"GET / HTTP/2.0\r\n\r\n");
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Shutdown
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        assert!(read_all(peer).starts_with(b"HTTP/1.1 505 "));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn header_filling_buffer_does_not_loop() {
+        let config = ServerConfig {
+            parser: ParserConfig {
+                max_request_line_size: 32,
+                max_header_section_size: 64,
+                body_buffer_size: 0,
+                ..ParserConfig::default()
+            },
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        // ヘッダーセクションがちょうどバッファの終わりで終わり, ボディを読み込む場所が無い
+        let request = b"POST /012345678901234 HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\n\
+X: 01234567890123456789012345678\r\n\r\n";
+        assert_eq!(request.len(), connection.buf.len());
+        write_all(peer, request);
+        let status = connection.fill_buffer();
+        assert_eq!(status, ReadStatus::Full);
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Shutdown
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        assert!(read_all(peer).starts_with(b"HTTP/1.1 431 "));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    /// ボディ全体を読み込んでから, そのまま送り返す
+    fn echo_body(connection: &mut Connection) -> Option<Response> {
+        let status = match connection.buffer_body(64) {
+            BodyResult::Complete => StatusCode::OK,
+            BodyResult::Again => return None,
+            BodyResult::TooLarge => StatusCode::CONTENT_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let mut response = Response::new(status);
+        response.set_body(connection.body_buf.clone());
+        Some(response)
+    }
+
+    #[test]
+    fn handler_reads_body() {
+        let config = ServerConfig {
+            server_token: None,
+            handler: echo_body,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(
+            peer,
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhel",
+        );
+        let status = connection.fill_buffer();
+        assert!(matches!(process(&mut connection, status), Progress::Again));
+        write_all(peer, b"lo");
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process_body(&mut connection, status),
+            Progress::Again
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        assert!(read_all(peer).ends_with(b"GMT\r\nContent-Length: 5\r\n\r\nhello"));
+
+        write_all(
+            peer,
+            b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+3\r\nabc\r\n",
+        );
+        let status = connection.fill_buffer();
+        assert!(matches!(process(&mut connection, status), Progress::Again));
+        write_all(peer, b"2\r\nde\r\n0\r\n\r\n");
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process_body(&mut connection, status),
+            Progress::Again
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        assert!(read_all(peer).ends_with(b"GMT\r\nContent-Length: 5\r\n\r\nabcde"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    /// ボディを読んだ結果を無視して応答する
+    fn ignore_body_result(connection: &mut Connection) -> Option<Response> {
+        connection.buffer_body(usize::MAX);
+        Some(Response::new(StatusCode::OK))
+    }
+
+    #[test]
+    fn body_limit_is_enforced_by_server() {
+        let config = ServerConfig {
+            client_max_body_size: Some(3),
+            handler: ignore_body_result,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(
+            peer,
+            b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+5\r\nhello\r\n0\r\n\r\n",
+        );
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Shutdown
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        assert!(read_all(peer).starts_with(b"HTTP/1.1 413 Content Too Large\r\n"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn continue_is_sent_when_handler_reads_body() {
+        let config = ServerConfig {
+            handler: echo_body,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(
+            peer,
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n",
+        );
+        let status = connection.fill_buffer();
+        assert!(matches!(process(&mut connection, status), Progress::Again));
+        assert_eq!(read_all(peer), b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        write_all(peer, b"hello");
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process_body(&mut connection, status),
+            Progress::Again
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        let out = read_all(peer);
+        assert!(out.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with(b"\r\n\r\nhello"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    /// ボディを読まずに拒否する
+    fn reject(_: &mut Connection) -> Option<Response> {
+        Some(Response::new(StatusCode::FORBIDDEN))
+    }
+
+    #[test]
+    fn continue_is_not_sent_when_handler_rejects() {
+        let config = ServerConfig {
+            handler: reject,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(
+            peer,
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n",
+        );
+        let status = connection.fill_buffer();
+        // ボディが送られてくるかどうか分からないので, 接続を閉じる
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Shutdown
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        let out = read_all(peer);
+        assert!(out.starts_with(b"HTTP/1.1 403 Forbidden\r\n"));
+        assert!(out.ends_with(b"Content-Length: 0\r\nConnection: close\r\n\r\n"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn unsupported_expectation_fails() {
+        let config = ServerConfig {
+            handler: echo_body,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(
+            peer,
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nExpect: 200-ok\r\n\r\n",
+        );
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Shutdown
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        assert!(read_all(peer).starts_with(b"HTTP/1.1 417 Expectation Failed\r\n"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    /// ボディを書き込まずにストリーミングのレスポンスを返す
+    fn unfinished_stream(_: &mut Connection) -> Option<Response> {
+        let mut response = Response::new(StatusCode::OK);
+        response.set_streaming();
+        Some(response)
+    }
+
+    #[test]
+    fn streaming_body_is_finished_before_next_request() {
+        let config = ServerConfig {
+            server_token: None,
+            handler: unfinished_stream,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(
+            peer,
+            b"GET /a HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\n\r\n",
+        );
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Continue
+        ));
+        assert!(matches!(process(&mut connection, status), Progress::Again));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+
+        let out = read_all(peer);
+        let chunked = b"Transfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        assert!(out.ends_with(chunked));
+        let responses = out
+            .windows(chunked.len())
+            .filter(|window| window == chunked)
+            .count();
+        assert_eq!(responses, 2);
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn head_response_has_no_body() {
+        let config = ServerConfig {
+            server_token: None,
+            handler: unfinished_stream,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(peer, b"HEAD / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let status = connection.fill_buffer();
+        assert!(matches!(process(&mut connection, status), Progress::Again));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        let out = read_all(peer);
+        assert!(out.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with(b"GMT\r\n\r\n"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn pipelined_requests_survive_reset() {
+        let config = ServerConfig {
+            server_token: None,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(
+            peer,
+            b"POST /a HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc\
+GET /b HTTP/1.1\r\nHost: a\r\n\r\n\
+GET /c HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        );
+        let status = connection.fill_buffer();
+        assert_eq!(status, ReadStatus::WouldBlock);
+
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Continue
+        ));
+        assert!(connection.buf.starts_with(b"GET /b HTTP/1.1\r\n"));
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Continue
+        ));
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Shutdown
+        ));
+        assert_eq!(connection.request().path(), b"/c");
+        assert_eq!(connection.requests, 3);
+        assert_eq!(connection.flush(), WriteStatus::Done);
+
+        let out = read_all(peer);
+        let responses = out
+            .windows(b"HTTP/1.1 204".len())
+            .filter(|window| window == b"HTTP/1.1 204")
+            .count();
+        assert_eq!(responses, 3);
+        assert!(out.ends_with(b"GMT\r\nConnection: close\r\n\r\n"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn close_after_keep_alive_requests() {
+        let config = ServerConfig {
+            keep_alive_requests: 2,
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        write_all(peer, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let status = connection.fill_buffer();
+        assert!(matches!(process(&mut connection, status), Progress::Again));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        assert!(connection.is_idle());
+        let out = read_all(peer);
+        assert!(out.ends_with(b"GMT\r\nServer: rashin\r\n\r\n"));
+
+        write_all(peer, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let status = connection.fill_buffer();
+        assert!(matches!(
+            process(&mut connection, status),
+            Progress::Shutdown
+        ));
+        assert_eq!(connection.flush(), WriteStatus::Done);
+        let out = read_all(peer);
+        assert!(out.ends_with(b"GMT\r\nServer: rashin\r\nConnection: close\r\n\r\n"));
+
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
+    }
+
+    #[test]
+    fn idle_connection_expires() {
+        let config = ServerConfig {
+            keep_alive_timeout: Duration::from_secs(5),
+            client_header_timeout: Duration::from_secs(10),
+            ..ServerConfig::default()
+        };
+        let (mut connection, peer) = connected_pair(config);
+        let now = connection.last_active;
+        assert!(connection.is_idle());
+        assert!(!connection.is_expired(now + Duration::from_secs(4)));
+        assert!(connection.is_expired(now + Duration::from_secs(5)));
+
+        // リクエストの途中でもデータが届かなければタイムアウトする
+        write_all(peer, b"GET / HTTP/1.1\r\n");
+        connection.fill_buffer();
+        assert!(!connection.is_idle());
+        let start = connection.request_start;
+        assert!(!connection.is_expired(start + Duration::from_secs(4)));
+        assert!(connection.is_expired(start + Duration::from_secs(5)));
+
+        // 少しずつ送り続けても, ヘッダーセクションを受信し終えなければタイムアウトする
+        connection.last_active = start + Duration::from_secs(9);
+        assert!(!connection.is_expired(start + Duration::from_secs(9)));
+        assert!(connection.is_expired(start + Duration::from_secs(10)));
+
+        // 送信待ちのレスポンスがあっても, 送信が進まなければタイムアウトする
+        connection.reset();
+        connection.filled = 0;
+        connection
+            .out_buf
+            .extend_from_slice(b"HTTP/1.1 204 No Content\r\n\r\n");
+        let now = connection.last_active;
+        assert!(!connection.is_idle());
+        assert!(!connection.is_expired(now + Duration::from_secs(4)));
+        assert!(connection.is_expired(now + Duration::from_secs(5)));
 
-        // Process Write Event
-        let send_str = String::from("HTTP/1.1 204 No Content\r\n\r\n");
-        let mut send_buf = send_str.clone().into_bytes();
-        log::debug!("Send: {}", &send_str);
-        syscall::write(fd, &mut send_buf).unwrap();
-
-        // Register Write Event
-        // event_map.insert(event_fd, write_event);
-        // let mut epoll_event = libc::epoll_event {
-        //     events: libc::EPOLLOUT as u32,
-        //     u64: event_fd as u64,
-        // };
-        // syscall::epoll_ctl(epoll_fd, libc::EPOLL_CTL_MOD, event_fd, Some(&mut epoll_event)).unwrap();
-        event.state = EventState::Shutdown;
-    } else {
-        println!("Connection is None.");
+        syscall::close(connection.fd).unwrap();
+        syscall::close(peer).unwrap();
     }
 }
@@ -4,4 +4,8 @@ use thiserror::Error;
 pub enum RashinErr {
     #[error("Syscall returns some error. errno = {0}")]
     SyscallError(i32),
+    #[error("Invalid {0} header field.")]
+    InvalidHeader(&'static str),
+    #[error("Ambiguous message framing. {0}")]
+    AmbiguousFraming(&'static str),
 }
@@ -1,10 +1,24 @@
+pub mod body;
+pub mod config;
+pub mod date;
+pub mod header_name;
+pub mod header_value;
 pub mod http_interface;
+pub mod multipart;
+pub mod parse_chunked_body;
 pub mod parse_request_header;
 pub mod parse_request_line;
+pub mod parse_request_target;
 mod parse_utility;
+pub mod request;
+pub mod response;
+pub mod status;
+pub mod uri;
 
 #[cfg(test)]
 mod parse_http_header {
+    use super::config::ParserConfig;
+    use super::header_name::KnownHeader;
     use super::http_interface::*;
     use super::parse_request_header::*;
     use super::parse_request_line::*;
@@ -21,18 +35,197 @@ mod parse_http_header {
         let mut cursor = Cursor::new(&buf);
 
         let mut http_header = HTTPHeader::new();
+        let config = ParserConfig::default();
         {
-            let result = parse_http_request_line(&mut cursor, &mut http_header, RequestLineState::Start);
+            let result = parse_http_request_line(
+                &mut cursor,
+                &mut http_header,
+                RequestLineState::Start,
+                &config,
+            );
             assert!(matches!(result, ParseResult::Complete));
-            assert_eq!(http_header.method(&buf), "GET");
+            assert_eq!(http_header.method(&buf), Method::Get);
         }
 
         {
             let mut field = Field::new();
-            let result = parse_http_request_header(&mut cursor, &mut field);
+            let result = parse_http_request_header(
+                &mut cursor,
+                &mut http_header,
+                &mut field,
+                RequestHeaderState::Start,
+                &config,
+            );
             assert!(matches!(result, ParseResult::Complete));
-            assert_eq!(field.name(&buf), "Host");
-            assert_eq!(field.value(&buf), "localhost:8080");
+            assert_eq!(field.name_bytes(&buf), b"Host");
+            assert_eq!(field.value(&buf), Ok("localhost:8080"));
         }
     }
+
+    #[test]
+    fn absolute_form_authority_takes_precedence_over_host() {
+        let buf = "\
+        GET http://example.com/index.html HTTP/1.1\r\n\
+        Host: localhost:8080\r\n\
+        "
+        .as_bytes()
+        .to_vec();
+        let mut cursor = Cursor::new(&buf);
+
+        let mut http_header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result = parse_http_request_line(
+            &mut cursor,
+            &mut http_header,
+            RequestLineState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+
+        let mut field = Field::new();
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut http_header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        process_reserved_header(&mut http_header, &field, &buf).unwrap();
+        assert_eq!(http_header.authority(&buf), Some(&b"example.com"[..]));
+    }
+
+    #[test]
+    fn origin_form_authority_comes_from_host() {
+        let buf = "\
+        GET /index.html HTTP/1.1\r\n\
+        Host: localhost:8080\r\n\
+        "
+        .as_bytes()
+        .to_vec();
+        let mut cursor = Cursor::new(&buf);
+
+        let mut http_header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result = parse_http_request_line(
+            &mut cursor,
+            &mut http_header,
+            RequestLineState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(http_header.authority(&buf), None);
+
+        let mut field = Field::new();
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut http_header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        process_reserved_header(&mut http_header, &field, &buf).unwrap();
+        assert_eq!(http_header.authority(&buf), Some(&b"localhost:8080"[..]));
+    }
+
+    fn parse_request(buf: &[u8]) -> HTTPHeader {
+        let mut cursor = Cursor::new(buf);
+        let mut http_header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result = parse_http_request_line(
+            &mut cursor,
+            &mut http_header,
+            RequestLineState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        loop {
+            let mut field = Field::new();
+            let result = parse_http_request_header(
+                &mut cursor,
+                &mut http_header,
+                &mut field,
+                RequestHeaderState::Start,
+                &config,
+            );
+            assert!(matches!(result, ParseResult::Complete));
+            if field.is_separator {
+                return http_header;
+            }
+        }
+    }
+
+    #[test]
+    fn lookup_headers_ignoring_case() {
+        let buf = b"\
+        GET / HTTP/1.1\r\n\
+        host: localhost\r\n\
+        Accept: text/html\r\n\
+        X-Forwarded-For: 192.0.2.1\r\n\
+        accept: application/json\r\n\
+        x-forwarded-for: 198.51.100.1\r\n\
+        \r\n";
+        let http_header = parse_request(buf);
+        assert_eq!(http_header.field_size, 5);
+
+        let host = http_header.known(KnownHeader::Host).unwrap();
+        assert_eq!(host.value(buf), Ok("localhost"));
+        assert_eq!(
+            http_header.get("HOST", buf).unwrap().value(buf),
+            Ok("localhost")
+        );
+        assert!(http_header.known(KnownHeader::ContentLength).is_none());
+        assert!(http_header.get("Content-Length", buf).is_none());
+
+        let accepts: Vec<_> = http_header
+            .known_all(KnownHeader::Accept)
+            .map(|field| field.value(buf).unwrap())
+            .collect();
+        assert_eq!(accepts, ["text/html", "application/json"]);
+        let accepts: Vec<_> = http_header
+            .get_all("ACCEPT", buf)
+            .map(|field| field.value(buf).unwrap())
+            .collect();
+        assert_eq!(accepts, ["text/html", "application/json"]);
+        assert_eq!(http_header.known_all(KnownHeader::Cookie).count(), 0);
+
+        let forwarded: Vec<_> = http_header
+            .get_all("X-FORWARDED-FOR", buf)
+            .map(|field| field.value(buf).unwrap())
+            .collect();
+        assert_eq!(forwarded, ["192.0.2.1", "198.51.100.1"]);
+        assert_eq!(
+            http_header.get("x-forwarded-for", buf).unwrap().value(buf),
+            Ok("192.0.2.1")
+        );
+        assert_eq!(http_header.get_all("X-Unknown", buf).count(), 0);
+    }
+
+    #[test]
+    fn normalized_host_for_virtual_host_selection() {
+        let buf = b"GET / HTTP/1.1\r\nHost: WWW.Example.com.:8080\r\n\r\n";
+        let mut http_header = parse_request(buf);
+        for i in 0..http_header.fields.len() {
+            let field = http_header.fields[i];
+            process_reserved_header(&mut http_header, &field, &buf).unwrap();
+        }
+        assert_eq!(
+            http_header.normalized_host(buf).unwrap(),
+            &b"www.example.com"[..]
+        );
+        assert_eq!(http_header.target_host.unwrap().port, Some(8080));
+
+        let buf = b"GET http://[::1]/ HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut http_header = parse_request(buf);
+        for i in 0..http_header.fields.len() {
+            let field = http_header.fields[i];
+            process_reserved_header(&mut http_header, &field, &buf).unwrap();
+        }
+        assert_eq!(http_header.normalized_host(buf).unwrap(), &b"[::1]"[..]);
+
+        let buf = b"GET / HTTP/1.1\r\n\r\n";
+        let http_header = parse_request(buf);
+        assert!(http_header.normalized_host(buf).is_none());
+    }
 }
@@ -0,0 +1,245 @@
+//! リクエストボディの読み込み
+//!
+//! ボディの長さはTransfer-EncodingまたはContent-Lengthで決まる。BodyReaderはこの違いを隠し,
+//! ハンドラーは読み込んだボディをバッファ上の範囲として順に受け取る。
+//!
+//! References:
+//! https://www.rfc-editor.org/rfc/rfc9112#name-message-body-length
+use std::io::Cursor;
+use std::ops::Range;
+
+use super::config::ParserConfig;
+use super::http_interface::{Field, HTTPHeader};
+use super::parse_chunked_body::{ChunkedDecoder, ChunkedResult, ChunkedState};
+use super::parse_utility::{advance, remaining};
+
+pub enum BodyResult {
+    /// buffer[range]がボディの一部である。続きを読むために再度呼び出す
+    Data(Range<usize>),
+    /// 入力が足りない。追加のデータを読み込んでから再度呼び出す
+    Again,
+    /// ボディを全て読み終えた
+    Complete,
+    Error,
+    /// ボディまたはchunkが上限を超えた
+    TooLarge,
+}
+
+#[derive(Clone, Debug)]
+enum Framing {
+    /// Content-Lengthで長さが決まっている。ボディが無い場合は0として扱う
+    Length {
+        remaining: u64,
+    },
+    Chunked(Box<ChunkedDecoder>),
+}
+
+/// リクエストボディを読み込むステートマシン。
+/// 入力が途中で途切れても, 追加のデータを読み込んでから続きを読める。
+#[derive(Clone, Debug)]
+pub struct BodyReader {
+    framing: Framing,
+    /// これまでに読み込んだボディのバイト数
+    received: u64,
+    /// ボディの最大長。Noneの場合は制限しない
+    limit: Option<u64>,
+}
+
+impl BodyReader {
+    /// ヘッダーからボディの長さの決め方を選ぶ。
+    /// validate_framingで曖昧なリクエストは拒否されている前提である。
+    /// ボディがlimitを超えた時点でTooLargeを返す。
+    pub fn new(header: &HTTPHeader, limit: Option<u64>) -> Self {
+        let framing = if header.is_chunked() {
+            Framing::Chunked(Box::default())
+        } else {
+            Framing::Length {
+                remaining: header.content_length.unwrap_or(0),
+            }
+        };
+        BodyReader {
+            framing,
+            received: 0,
+            limit,
+        }
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        matches!(self.framing, Framing::Chunked(_))
+    }
+
+    /// これまでに読み込んだボディのバイト数
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn is_complete(&self) -> bool {
+        match &self.framing {
+            Framing::Length { remaining } => *remaining == 0,
+            Framing::Chunked(decoder) => matches!(decoder.state, ChunkedState::End),
+        }
+    }
+
+    /// chunkedのtrailer sectionで送られたヘッダー
+    pub fn trailers(&self) -> &[Field] {
+        match &self.framing {
+            Framing::Length { .. } => &[],
+            Framing::Chunked(decoder) => decoder.trailers(),
+        }
+    }
+
+    /// 読み終えたボディをバッファから取り除いても良いかどうか。
+    /// trailer sectionを読んでいる間はFieldがバッファ上の位置を保持しているので詰められない。
+    pub fn can_compact(&self) -> bool {
+        match &self.framing {
+            Framing::Length { .. } => true,
+            Framing::Chunked(decoder) => {
+                !matches!(decoder.state, ChunkedState::Trailer(_) | ChunkedState::End)
+            }
+        }
+    }
+
+    /// cursorの位置からボディを読み込む。
+    /// ボディは1回の呼び出しにつき1つの範囲として返すので, CompleteかAgainになるまで繰り返し呼び出す。
+    pub fn read<T: AsRef<[u8]>>(
+        &mut self,
+        cursor: &mut Cursor<T>,
+        config: &ParserConfig,
+    ) -> BodyResult {
+        let result = match &mut self.framing {
+            Framing::Length { remaining: 0 } => return BodyResult::Complete,
+            Framing::Length { remaining: length } => {
+                let available = remaining(cursor).len();
+                if available == 0 {
+                    return BodyResult::Again;
+                }
+                let n = (available as u64).min(*length) as usize;
+                let start = cursor.position() as usize;
+                advance(cursor, n);
+                *length -= n as u64;
+                BodyResult::Data(start..start + n)
+            }
+            Framing::Chunked(decoder) => match decoder.decode(cursor, config) {
+                ChunkedResult::Data(range) => BodyResult::Data(range),
+                ChunkedResult::Again => BodyResult::Again,
+                ChunkedResult::Complete => BodyResult::Complete,
+                ChunkedResult::Error => BodyResult::Error,
+                ChunkedResult::TooLarge => BodyResult::TooLarge,
+            },
+        };
+        if let BodyResult::Data(range) = &result {
+            self.received += range.len() as u64;
+            if self.limit.is_some_and(|limit| self.received > limit) {
+                return BodyResult::TooLarge;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::header_value::TransferCoding;
+
+    fn read_all(buf: &[u8], reader: &mut BodyReader) -> Option<Vec<u8>> {
+        let config = ParserConfig::default();
+        let mut cursor = Cursor::new(buf);
+        let mut body = Vec::new();
+        loop {
+            match reader.read(&mut cursor, &config) {
+                BodyResult::Data(range) => body.extend_from_slice(&buf[range]),
+                BodyResult::Complete => return Some(body),
+                _ => return None,
+            }
+        }
+    }
+
+    #[test]
+    fn read_content_length_body() {
+        let mut header = HTTPHeader::new();
+        header.content_length = Some(5);
+        let mut reader = BodyReader::new(&header, None);
+        assert!(!reader.is_chunked());
+
+        let buf = b"hello, next request";
+        let body = read_all(buf, &mut reader).unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(reader.received(), 5);
+        assert!(reader.is_complete());
+    }
+
+    #[test]
+    fn read_chunked_body() {
+        let mut header = HTTPHeader::new();
+        header.transfer_encoding.push(TransferCoding::Chunked);
+        let mut reader = BodyReader::new(&header, None);
+        assert!(reader.is_chunked());
+
+        let buf = b"5\r\nhello\r\n0\r\nX-Sum: 1\r\n\r\n";
+        let body = read_all(buf, &mut reader).unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(reader.received(), 5);
+        assert_eq!(reader.trailers().len(), 1);
+        assert!(!reader.can_compact());
+    }
+
+    #[test]
+    fn request_without_body_is_complete() {
+        let header = HTTPHeader::new();
+        let mut reader = BodyReader::new(&header, None);
+        assert!(reader.is_complete());
+        assert_eq!(read_all(b"GET / HTTP/1.1\r\n", &mut reader).unwrap(), b"");
+    }
+
+    #[test]
+    fn read_body_across_inputs() {
+        let mut header = HTTPHeader::new();
+        header.content_length = Some(8);
+        let mut reader = BodyReader::new(&header, None);
+        let config = ParserConfig::default();
+
+        let buf = b"abc";
+        let mut cursor = Cursor::new(&buf[..]);
+        assert!(matches!(reader.read(&mut cursor, &config), BodyResult::Data(r) if r == (0..3)));
+        assert!(matches!(
+            reader.read(&mut cursor, &config),
+            BodyResult::Again
+        ));
+
+        // 読み終えた分を詰めた後の新しい入力
+        let buf = b"defgh";
+        let mut cursor = Cursor::new(&buf[..]);
+        assert!(matches!(reader.read(&mut cursor, &config), BodyResult::Data(r) if r == (0..5)));
+        assert!(matches!(
+            reader.read(&mut cursor, &config),
+            BodyResult::Complete
+        ));
+        assert_eq!(reader.received(), 8);
+    }
+
+    #[test]
+    fn chunked_body_over_limit() {
+        let mut header = HTTPHeader::new();
+        header.transfer_encoding.push(TransferCoding::Chunked);
+        let mut reader = BodyReader::new(&header, Some(6));
+        let buf = b"3\r\nabc\r\n3\r\ndef\r\n1\r\ng\r\n0\r\n\r\n";
+        let config = ParserConfig::default();
+        let mut cursor = Cursor::new(&buf[..]);
+        assert!(matches!(
+            reader.read(&mut cursor, &config),
+            BodyResult::Data(_)
+        ));
+        assert!(matches!(
+            reader.read(&mut cursor, &config),
+            BodyResult::Data(_)
+        ));
+        assert!(matches!(
+            reader.read(&mut cursor, &config),
+            BodyResult::TooLarge
+        ));
+
+        let mut reader = BodyReader::new(&header, Some(7));
+        assert_eq!(read_all(buf, &mut reader).unwrap(), b"abcdefg");
+    }
+}
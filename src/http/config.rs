@@ -0,0 +1,86 @@
+/// obs-fold(行頭の空白による複数行のヘッダー)の扱い
+/// RFC9112ではサーバーは400で拒否するか, 空白に置き換えて解釈するかのどちらかを選ぶ。
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9112#name-obsolete-line-folding
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObsFold {
+    /// パースエラーとして扱い, 400を返す
+    Reject,
+    /// 直前のヘッダーの値の続きとして扱い, 改行をSPに置き換える
+    Replace,
+}
+
+/// パーサーの動作を決める設定
+///
+/// RFC9112で受け入れても良いとされている古いクライアント向けの書式をどこまで許容するかを切り替える。
+/// インターネットに面したサーバーではstrict, 社内の古いクライアント向けにはlenientを使う。
+/// Defaultは, RFCがMUSTで禁止しているもの以外を許容する。
+#[derive(Clone, Debug)]
+pub struct ParserConfig {
+    /// リクエストラインの最大長(改行と先頭の空行を含む)。超えた場合は414 URI Too Longを返す
+    pub max_request_line_size: usize,
+    /// ヘッダー1行の最大長(改行を含む)。超えた場合は431を返す
+    pub max_header_field_size: usize,
+    /// 空行を含むヘッダーセクション全体の最大長。超えた場合は431を返す
+    pub max_header_section_size: usize,
+    /// ヘッダーの最大数。超えた場合は431を返す
+    pub max_header_fields: usize,
+    /// chunkedで送られるボディの1つのchunkの最大長
+    pub max_chunk_size: u64,
+    /// ヘッダーセクションの後ろでボディを読み込むために確保するバッファのサイズ
+    pub body_buffer_size: usize,
+    /// CRを伴わないLFだけの改行を受け付ける
+    pub allow_bare_lf: bool,
+    /// リクエストラインの前の空行を読み飛ばす
+    pub allow_leading_empty_lines: bool,
+    /// field-nameと":"の間の空白を取り除いて受け付ける。RFC9112では400で拒否しなければならない
+    pub allow_space_before_colon: bool,
+    pub obs_fold: ObsFold,
+}
+
+impl ParserConfig {
+    /// RFC9112の書式に厳密に従うリクエストだけを受け付ける
+    pub fn strict() -> Self {
+        ParserConfig {
+            allow_bare_lf: false,
+            allow_leading_empty_lines: false,
+            allow_space_before_colon: false,
+            obs_fold: ObsFold::Reject,
+            ..ParserConfig::default()
+        }
+    }
+
+    /// 古いクライアントが送る書式も可能な限り受け付ける
+    pub fn lenient() -> Self {
+        ParserConfig {
+            allow_bare_lf: true,
+            allow_leading_empty_lines: true,
+            allow_space_before_colon: true,
+            obs_fold: ObsFold::Replace,
+            ..ParserConfig::default()
+        }
+    }
+
+    /// リクエストラインとヘッダーセクション, ボディの読み込みに必要なバッファのサイズ
+    pub fn buffer_size(&self) -> usize {
+        self.max_request_line_size + self.max_header_section_size + self.body_buffer_size
+    }
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            max_request_line_size: 8 * 1024,
+            max_header_field_size: 8 * 1024,
+            max_header_section_size: 16 * 1024,
+            max_header_fields: 100,
+            max_chunk_size: 16 * 1024 * 1024,
+            body_buffer_size: 16 * 1024,
+            allow_bare_lf: true,
+            allow_leading_empty_lines: true,
+            allow_space_before_colon: false,
+            obs_fold: ObsFold::Reject,
+        }
+    }
+}
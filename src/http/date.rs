@@ -0,0 +1,482 @@
+//! HTTP-dateの生成とパース
+//!
+//! 送信する日時は常にIMF-fixdateで生成するが, 受信した日時は古い2つの形式も受け付ける。
+//!
+//! HTTP-date = IMF-fixdate / obs-date
+//! IMF-fixdate = day-name "," SP date1 SP time-of-day SP GMT
+//!               例: Sun, 06 Nov 1994 08:49:37 GMT
+//! rfc850-date = day-name-l "," SP date2 SP time-of-day SP GMT
+//!               例: Sunday, 06-Nov-94 08:49:37 GMT
+//! asctime-date = day-name SP date3 SP time-of-day SP year
+//!               例: Sun Nov  6 08:49:37 1994
+//!
+//! References:
+//! https://www.rfc-editor.org/rfc/rfc9110#name-date-time-formats
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// IMF-fixdateの長さ
+pub const IMF_FIXDATE_LEN: usize = 29;
+
+const DAY_NAMES: [&[u8; 3]; 7] = [b"Sun", b"Mon", b"Tue", b"Wed", b"Thu", b"Fri", b"Sat"];
+const DAY_NAMES_LONG: [&[u8]; 7] = [
+    b"Sunday",
+    b"Monday",
+    b"Tuesday",
+    b"Wednesday",
+    b"Thursday",
+    b"Friday",
+    b"Saturday",
+];
+const MONTH_NAMES: [&[u8; 3]; 12] = [
+    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
+];
+
+/// 1970-01-01からの日数を(年, 月, 日)に変換する
+///
+/// References:
+/// https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// (年, 月, 日)を1970-01-01からの日数に変換する
+///
+/// References:
+/// https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn write_two_digits(out: &mut [u8], value: u32) {
+    out[0] = b'0' + (value / 10 % 10) as u8;
+    out[1] = b'0' + (value % 10) as u8;
+}
+
+/// UNIX時間の秒をIMF-fixdateに変換する。
+/// 10000年以降の日付は表現できないので9999年12月31日として扱う。
+pub fn imf_fixdate(secs: u64) -> [u8; IMF_FIXDATE_LEN] {
+    // 9999-12-31T23:59:59Z
+    let secs = secs.min(253_402_300_799);
+    let days = (secs / 86400) as i64;
+    let time = (secs % 86400) as u32;
+    let (year, month, day) = civil_from_days(days);
+
+    let mut out = *b"Thu, 01 Jan 1970 00:00:00 GMT";
+    // 1970-01-01は木曜日
+    out[0..3].copy_from_slice(DAY_NAMES[((days + 4) % 7) as usize]);
+    write_two_digits(&mut out[5..7], day);
+    out[8..11].copy_from_slice(MONTH_NAMES[month as usize - 1]);
+    write_two_digits(&mut out[12..14], (year / 100) as u32);
+    write_two_digits(&mut out[14..16], (year % 100) as u32);
+    write_two_digits(&mut out[17..19], time / 3600);
+    write_two_digits(&mut out[20..22], time / 60 % 60);
+    write_two_digits(&mut out[23..25], time % 60);
+    out
+}
+
+/// HTTP-dateをUNIX時間の秒に変換する。形式が正しくない場合はNoneを返す。
+/// rfc850-dateの2桁の年は現在時刻を基準に解釈する。
+pub fn parse_http_date(input: &[u8]) -> Option<i64> {
+    parse_http_date_at(input, unix_time_now())
+}
+
+/// nowをUNIX時間の秒として, HTTP-dateをUNIX時間の秒に変換する。
+/// day-nameが日付と一致しなくても, 日付と時刻が正しければ受け付ける。
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#section-5.6.7
+pub fn parse_http_date_at(input: &[u8], now: u64) -> Option<i64> {
+    let date = if input.get(3) == Some(&b',') {
+        parse_imf_fixdate(input)?
+    } else if input.get(3) == Some(&b' ') {
+        parse_asctime_date(input)?
+    } else {
+        parse_rfc850_date(input, now)?
+    };
+    date.to_unix_time()
+}
+
+/// パースした日時。範囲の検証はto_unix_timeで行う
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl DateTime {
+    /// time-of-dayのsecondは閏秒のため60まで許される
+    fn to_unix_time(&self) -> Option<i64> {
+        if !(1..=12).contains(&self.month)
+            || self.day == 0
+            || self.day > days_in_month(self.year, self.month)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 60
+        {
+            return None;
+        }
+        let days = days_from_civil(self.year, self.month, self.day);
+        Some(
+            days * 86400
+                + i64::from(self.hour) * 3600
+                + i64::from(self.minute) * 60
+                + i64::from(self.second),
+        )
+    }
+}
+
+fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn parse_digits(input: &[u8]) -> Option<u32> {
+    if input.is_empty() || !input.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(
+        input
+            .iter()
+            .fold(0, |value, &c| value * 10 + u32::from(c - b'0')),
+    )
+}
+
+fn parse_month(input: &[u8]) -> Option<u32> {
+    MONTH_NAMES
+        .iter()
+        .position(|&name| name == input)
+        .map(|i| i as u32 + 1)
+}
+
+/// time-of-day = hour ":" minute ":" second
+fn parse_time_of_day(input: &[u8]) -> Option<(u32, u32, u32)> {
+    if input.len() != 8 || input[2] != b':' || input[5] != b':' {
+        return None;
+    }
+    Some((
+        parse_digits(&input[0..2])?,
+        parse_digits(&input[3..5])?,
+        parse_digits(&input[6..8])?,
+    ))
+}
+
+/// IMF-fixdate = day-name "," SP date1 SP time-of-day SP GMT
+/// date1 = day SP month SP year
+fn parse_imf_fixdate(input: &[u8]) -> Option<DateTime> {
+    if input.len() != IMF_FIXDATE_LEN
+        || !DAY_NAMES.iter().any(|&name| name == &input[0..3])
+        || &input[3..5] != b", "
+        || input[7] != b' '
+        || input[11] != b' '
+        || input[16] != b' '
+        || &input[25..] != b" GMT"
+    {
+        return None;
+    }
+    let (hour, minute, second) = parse_time_of_day(&input[17..25])?;
+    Some(DateTime {
+        year: i64::from(parse_digits(&input[12..16])?),
+        month: parse_month(&input[8..11])?,
+        day: parse_digits(&input[5..7])?,
+        hour,
+        minute,
+        second,
+    })
+}
+
+/// rfc850-date = day-name-l "," SP date2 SP time-of-day SP GMT
+/// date2 = day "-" month "-" 2DIGIT
+///
+/// 2桁の年は, 50年より先の未来に見える場合は同じ下2桁を持つ直近の過去の年として解釈する。
+fn parse_rfc850_date(input: &[u8], now: u64) -> Option<DateTime> {
+    let comma = input.iter().position(|&c| c == b',')?;
+    if !DAY_NAMES_LONG.iter().any(|&name| name == &input[..comma]) {
+        return None;
+    }
+    let rest = &input[comma..];
+    if rest.len() != 24
+        || &rest[..2] != b", "
+        || rest[4] != b'-'
+        || rest[8] != b'-'
+        || rest[11] != b' '
+        || &rest[20..] != b" GMT"
+    {
+        return None;
+    }
+    let (hour, minute, second) = parse_time_of_day(&rest[12..20])?;
+    Some(DateTime {
+        year: expand_two_digit_year(parse_digits(&rest[9..11])?, now),
+        month: parse_month(&rest[5..8])?,
+        day: parse_digits(&rest[2..4])?,
+        hour,
+        minute,
+        second,
+    })
+}
+
+/// 2桁の年を現在の年から50年後までの範囲で, 下2桁が一致する年に変換する
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#section-5.6.7-11
+fn expand_two_digit_year(year: u32, now: u64) -> i64 {
+    let (current, _, _) = civil_from_days((now / 86400) as i64);
+    let year = current - current.rem_euclid(100) + i64::from(year);
+    if year > current + 50 {
+        year - 100
+    } else if year <= current - 50 {
+        year + 100
+    } else {
+        year
+    }
+}
+
+/// asctime-date = day-name SP date3 SP time-of-day SP year
+/// date3 = month SP ( 2DIGIT / ( SP DIGIT ) )
+fn parse_asctime_date(input: &[u8]) -> Option<DateTime> {
+    if input.len() != 24
+        || !DAY_NAMES.iter().any(|&name| name == &input[0..3])
+        || input[3] != b' '
+        || input[7] != b' '
+        || input[10] != b' '
+        || input[19] != b' '
+    {
+        return None;
+    }
+    let day = match input[8] {
+        b' ' => parse_digits(&input[9..10])?,
+        _ => parse_digits(&input[8..10])?,
+    };
+    let (hour, minute, second) = parse_time_of_day(&input[11..19])?;
+    Some(DateTime {
+        year: i64::from(parse_digits(&input[20..24])?),
+        month: parse_month(&input[4..7])?,
+        day,
+        hour,
+        minute,
+        second,
+    })
+}
+
+/// 現在時刻のIMF-fixdateを保持し, 秒が変わった時だけ作り直す。
+/// Dateヘッダーをレスポンスごとに生成しないために使う。
+#[derive(Clone, Debug)]
+pub struct DateCache {
+    secs: u64,
+    value: [u8; IMF_FIXDATE_LEN],
+}
+
+impl DateCache {
+    pub fn new() -> Self {
+        DateCache {
+            secs: 0,
+            value: imf_fixdate(0),
+        }
+    }
+
+    /// UNIX時間の秒に対応するIMF-fixdateを返す
+    pub fn get(&mut self, secs: u64) -> &[u8; IMF_FIXDATE_LEN] {
+        if secs != self.secs {
+            self.secs = secs;
+            self.value = imf_fixdate(secs);
+        }
+        &self.value
+    }
+
+    /// 現在時刻のIMF-fixdateを返す
+    pub fn now(&mut self) -> &[u8; IMF_FIXDATE_LEN] {
+        self.get(unix_time_now())
+    }
+}
+
+impl Default for DateCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_imf_fixdate() {
+        assert_eq!(&imf_fixdate(0), b"Thu, 01 Jan 1970 00:00:00 GMT");
+        // RFC9110の例
+        assert_eq!(&imf_fixdate(784_111_777), b"Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(&imf_fixdate(951_782_400), b"Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(&imf_fixdate(u64::MAX), b"Fri, 31 Dec 9999 23:59:59 GMT");
+    }
+
+    #[test]
+    fn date_cache_refreshes_each_second() {
+        let mut cache = DateCache::new();
+        assert_eq!(cache.get(784_111_777), b"Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(cache.get(784_111_777), b"Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(cache.get(784_111_778), b"Sun, 06 Nov 1994 08:49:38 GMT");
+        assert_eq!(cache.now().len(), IMF_FIXDATE_LEN);
+    }
+
+    /// 2026-10-17T00:00:00Z
+    const NOW: u64 = 1_792_195_200;
+    /// Sun, 06 Nov 1994 08:49:37 GMT
+    const EXAMPLE: i64 = 784_111_777;
+
+    fn parse(input: &str) -> Option<i64> {
+        parse_http_date_at(input.as_bytes(), NOW)
+    }
+
+    #[test]
+    fn parse_three_formats() {
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(EXAMPLE));
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), Some(EXAMPLE));
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), Some(EXAMPLE));
+        assert_eq!(parse("Sun Nov 06 08:49:37 1994"), Some(EXAMPLE));
+        assert_eq!(parse("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse("Wed, 31 Dec 1969 23:59:59 GMT"), Some(-1));
+    }
+
+    #[test]
+    fn parse_and_format_round_trip() {
+        // 閏年の前後を含め, 1日ずつずらした日時が元に戻る
+        let start = days_from_civil(1995, 12, 25);
+        for days in start..start + 365 * 12 {
+            let secs = days as u64 * 86400 + 45_296;
+            let formatted = imf_fixdate(secs);
+            assert_eq!(parse_http_date_at(&formatted, NOW), Some(secs as i64));
+        }
+    }
+
+    #[test]
+    fn civil_conversion_is_consistent() {
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert!(day >= 1 && day <= days_in_month(year, month));
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!(is_leap_year(1996));
+        assert!(is_leap_year(2000));
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2100));
+        assert!(!is_leap_year(2023));
+
+        assert!(parse("Thu, 29 Feb 1996 00:00:00 GMT").is_some());
+        assert!(parse("Tue, 29 Feb 2000 00:00:00 GMT").is_some());
+        assert!(parse("Thu, 29 Feb 2024 12:00:00 GMT").is_some());
+        assert_eq!(parse("Thu, 29 Feb 1900 00:00:00 GMT"), None);
+        assert_eq!(parse("Mon, 29 Feb 2100 00:00:00 GMT"), None);
+        assert_eq!(parse("Wed, 29 Feb 2023 00:00:00 GMT"), None);
+        assert_eq!(parse("Thu, 30 Feb 2024 00:00:00 GMT"), None);
+        assert_eq!(
+            parse("Fri, 01 Mar 2024 00:00:00 GMT"),
+            parse("Thu, 29 Feb 2024 00:00:00 GMT").map(|secs| secs + 86400)
+        );
+        assert_eq!(
+            parse("Thursday, 29-Feb-24 00:00:00 GMT"),
+            parse("Thu, 29 Feb 2024 00:00:00 GMT")
+        );
+        assert_eq!(parse("Wednesday, 29-Feb-23 00:00:00 GMT"), None);
+        assert_eq!(
+            parse("Thu Feb 29 00:00:00 1996"),
+            parse("Thu, 29 Feb 1996 00:00:00 GMT")
+        );
+        assert_eq!(parse("Thu Feb 29 00:00:00 1900"), None);
+    }
+
+    #[test]
+    fn two_digit_years() {
+        let year = |input: &str| parse(input).map(|secs| civil_from_days(secs.div_euclid(86400)).0);
+        // 現在は2026年なので, 2076年までは未来として, それより先は過去として解釈する
+        assert_eq!(year("Saturday, 17-Oct-26 00:00:00 GMT"), Some(2026));
+        assert_eq!(year("Monday, 01-Jan-00 00:00:00 GMT"), Some(2000));
+        assert_eq!(year("Sunday, 06-Nov-94 08:49:37 GMT"), Some(1994));
+        assert_eq!(year("Tuesday, 01-Jan-70 00:00:00 GMT"), Some(2070));
+        assert_eq!(year("Friday, 01-Jan-76 00:00:00 GMT"), Some(2076));
+        assert_eq!(year("Friday, 01-Jan-77 00:00:00 GMT"), Some(1977));
+        assert_eq!(year("Friday, 01-Jan-99 00:00:00 GMT"), Some(1999));
+
+        assert_eq!(expand_two_digit_year(76, NOW), 2076);
+        assert_eq!(expand_two_digit_year(77, NOW), 1977);
+        // 2099年に受信した場合
+        let now = days_from_civil(2099, 6, 1) as u64 * 86400;
+        assert_eq!(expand_two_digit_year(99, now), 2099);
+        assert_eq!(expand_two_digit_year(49, now), 2149);
+        assert_eq!(expand_two_digit_year(50, now), 2050);
+        assert_eq!(expand_two_digit_year(0, now), 2100);
+    }
+
+    #[test]
+    fn invalid_dates_should_be_rejected() {
+        for input in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 08:49:37 gmt",
+            "sun, 06 Nov 1994 08:49:37 GMT",
+            "Sun, 06 nov 1994 08:49:37 GMT",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Sun,  06 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 94 08:49:37 GMT",
+            "Sun, 06 Nov 1994 8:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:60:00 GMT",
+            "Sun, 06 Nov 1994 08:49:61 GMT",
+            "Sun, 00 Nov 1994 08:49:37 GMT",
+            "Sun, 31 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49:37 GMT ",
+            "Sun, 06 Nov +994 08:49:37 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+            "Sun, 06-Nov-94 08:49:37 GMT",
+            "Sunday, 06 Nov 94 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 UTC",
+            "Sun Nov 6 08:49:37 1994",
+            "Sun Nov  6 08:49:37 94",
+            "Sun Nov  6 08:49:37 1994 GMT",
+            "Sun Nov 31 08:49:37 1994",
+            "Sun  Nov 6 08:49:37 1994",
+        ] {
+            assert_eq!(parse(input), None, "{}", input);
+        }
+        // day-nameが日付と一致しなくても受け付け, 閏秒も受け付ける
+        assert_eq!(parse("Mon, 06 Nov 1994 08:49:37 GMT"), Some(EXAMPLE));
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:60 GMT"), Some(EXAMPLE + 23));
+    }
+}
@@ -0,0 +1,106 @@
+//! サーバーが解釈するヘッダー名の一覧
+//!
+//! パース時にfield-nameをここで分類しておき, HTTPHeaderはヘッダーごとに現れた位置を
+//! 記録する。ハンドラーはヘッダーの一覧を走査せずにこれらのヘッダーを参照できる。
+
+/// よく使われるヘッダー
+/// field-nameは大文字・小文字を区別しない。
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-field-names
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KnownHeader {
+    Host,
+    ContentLength,
+    TransferEncoding,
+    Connection,
+    Expect,
+    Upgrade,
+    Te,
+    ContentType,
+    Accept,
+    AcceptEncoding,
+    Authorization,
+    Cookie,
+    UserAgent,
+}
+
+impl KnownHeader {
+    /// KnownHeaderの種類の数
+    pub const COUNT: usize = 13;
+
+    const ALL: [KnownHeader; KnownHeader::COUNT] = [
+        KnownHeader::Host,
+        KnownHeader::ContentLength,
+        KnownHeader::TransferEncoding,
+        KnownHeader::Connection,
+        KnownHeader::Expect,
+        KnownHeader::Upgrade,
+        KnownHeader::Te,
+        KnownHeader::ContentType,
+        KnownHeader::Accept,
+        KnownHeader::AcceptEncoding,
+        KnownHeader::Authorization,
+        KnownHeader::Cookie,
+        KnownHeader::UserAgent,
+    ];
+
+    /// field-nameからKnownHeaderを求める。一覧に無い場合はNoneを返す。
+    pub fn from_name(name: &[u8]) -> Option<KnownHeader> {
+        KnownHeader::ALL
+            .iter()
+            .find(|header| header.as_str().as_bytes().eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KnownHeader::Host => "Host",
+            KnownHeader::ContentLength => "Content-Length",
+            KnownHeader::TransferEncoding => "Transfer-Encoding",
+            KnownHeader::Connection => "Connection",
+            KnownHeader::Expect => "Expect",
+            KnownHeader::Upgrade => "Upgrade",
+            KnownHeader::Te => "TE",
+            KnownHeader::ContentType => "Content-Type",
+            KnownHeader::Accept => "Accept",
+            KnownHeader::AcceptEncoding => "Accept-Encoding",
+            KnownHeader::Authorization => "Authorization",
+            KnownHeader::Cookie => "Cookie",
+            KnownHeader::UserAgent => "User-Agent",
+        }
+    }
+
+    /// HTTPHeaderの索引で使う位置
+    pub(crate) fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_ignores_case() {
+        assert_eq!(KnownHeader::from_name(b"host"), Some(KnownHeader::Host));
+        assert_eq!(
+            KnownHeader::from_name(b"CONTENT-LENGTH"),
+            Some(KnownHeader::ContentLength)
+        );
+        assert_eq!(KnownHeader::from_name(b"te"), Some(KnownHeader::Te));
+        assert_eq!(KnownHeader::from_name(b"X-Custom"), None);
+        assert_eq!(KnownHeader::from_name(b"Hos"), None);
+    }
+
+    #[test]
+    fn index_matches_declaration_order() {
+        for (i, header) in KnownHeader::ALL.iter().enumerate() {
+            assert_eq!(header.index(), i);
+            assert_eq!(
+                KnownHeader::from_name(header.as_str().as_bytes()),
+                Some(*header)
+            );
+        }
+    }
+}
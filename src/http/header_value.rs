@@ -0,0 +1,525 @@
+//! ヘッダーの値に共通する構文と, サーバーが解釈するヘッダーの値の型
+//!
+//! obs-foldを含む値はCR LFがそのまま残っているので, CRとLFも空白として扱う。
+//!
+//! References:
+//! https://www.rfc-editor.org/rfc/rfc9110#name-common-rules-for-defining-f
+use std::borrow::Cow;
+
+use super::parse_utility::{is_tchar, is_vchar};
+
+fn is_ows(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+fn trim_start(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|&c| !is_ows(c))
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let bytes = trim_start(bytes);
+    let end = bytes.iter().rposition(|&c| !is_ows(c)).map_or(0, |n| n + 1);
+    &bytes[..end]
+}
+
+/// 先頭のtokenの長さ
+fn token_len(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .position(|&c| !is_tchar(c))
+        .unwrap_or(bytes.len())
+}
+
+/// `#element`で定義されたリストの要素を順に返す。
+/// quoted-stringの中の","では区切らず, 空の要素は読み飛ばす。
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-lists-rule-abnf-extension
+pub struct ListElements<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for ListElements<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.rest.is_empty() {
+            let mut in_quote = false;
+            let mut escaped = false;
+            let mut end = self.rest.len();
+            for (i, &c) in self.rest.iter().enumerate() {
+                if escaped {
+                    escaped = false;
+                } else if in_quote && c == b'\\' {
+                    escaped = true;
+                } else if c == b'"' {
+                    in_quote = !in_quote;
+                } else if !in_quote && c == b',' {
+                    end = i;
+                    break;
+                }
+            }
+            let element = trim(&self.rest[..end]);
+            self.rest = self.rest.get(end + 1..).unwrap_or(&[]);
+            if !element.is_empty() {
+                return Some(element);
+            }
+        }
+        None
+    }
+}
+
+pub fn list_elements(value: &[u8]) -> ListElements<'_> {
+    ListElements { rest: value }
+}
+
+/// 要素を先頭のtokenと残りに分ける。tokenが空の場合はNoneを返す。
+fn split_token(element: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = token_len(element);
+    if len == 0 {
+        return None;
+    }
+    Some(element.split_at(len))
+}
+
+/// quoted-stringを読み込み, エスケープを取り除いた値と残りを返す。
+///
+/// quoted-string = DQUOTE *( qdtext / quoted-pair ) DQUOTE
+/// qdtext = HTAB / SP / %x21 / %x23-5B / %x5D-7E / obs-text
+/// quoted-pair = "\" ( HTAB / SP / VCHAR / obs-text )
+fn parse_quoted_string(input: &[u8]) -> Option<(Cow<'_, [u8]>, &[u8])> {
+    if input.first() != Some(&b'"') {
+        return None;
+    }
+    let mut unescaped: Option<Vec<u8>> = None;
+    let mut i = 1;
+    while i < input.len() {
+        let c = input[i];
+        match c {
+            b'"' => {
+                let value = match unescaped {
+                    Some(value) => Cow::Owned(value),
+                    None => Cow::Borrowed(&input[1..i]),
+                };
+                return Some((value, &input[i + 1..]));
+            }
+            b'\\' => {
+                let escaped = *input.get(i + 1)?;
+                if !(escaped == b' ' || escaped == b'\t' || is_vchar(escaped) || escaped >= 0x80) {
+                    return None;
+                }
+                unescaped
+                    .get_or_insert_with(|| input[1..i].to_vec())
+                    .push(escaped);
+                i += 2;
+                continue;
+            }
+            b' ' | b'\t' | 0x21 | 0x23..=0x5b | 0x5d..=0x7e | 0x80..=0xff => {
+                if let Some(value) = unescaped.as_mut() {
+                    value.push(c);
+                }
+            }
+            _ => return None,
+        }
+        i += 1;
+    }
+    None
+}
+
+/// parameterの名前と値の組
+pub type Parameter<'a> = (&'a [u8], Cow<'a, [u8]>);
+
+/// parametersを名前と値の組に分解する。構文が正しくない場合はNoneを返す。
+///
+/// parameters = *( OWS ";" OWS [ parameter ] )
+/// parameter = parameter-name "=" parameter-value
+/// parameter-value = ( token / quoted-string )
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-parameters
+pub fn parse_parameters(input: &[u8]) -> Option<Vec<Parameter<'_>>> {
+    let mut parameters = Vec::new();
+    let mut rest = trim_start(input);
+    while !rest.is_empty() {
+        if rest[0] != b';' {
+            return None;
+        }
+        rest = trim_start(&rest[1..]);
+        if rest.is_empty() || rest[0] == b';' {
+            continue;
+        }
+        let (name, after_name) = split_token(rest)?;
+        if after_name.first() != Some(&b'=') {
+            return None;
+        }
+        let after_eq = &after_name[1..];
+        let (value, after_value) = if after_eq.first() == Some(&b'"') {
+            parse_quoted_string(after_eq)?
+        } else {
+            let (token, after_token) = split_token(after_eq)?;
+            (Cow::Borrowed(token), after_token)
+        };
+        parameters.push((name, value));
+        rest = trim_start(after_value);
+    }
+    Some(parameters)
+}
+
+/// `token *( OWS ";" OWS parameter )`の形式の値をtokenとparametersに分解する。
+/// Content-Dispositionのように, 種類の後にparameterが続くヘッダーで使う。
+pub fn parse_token_with_parameters(value: &[u8]) -> Option<(&[u8], Vec<Parameter<'_>>)> {
+    let (token, rest) = split_token(trim(value))?;
+    Some((token, parse_parameters(rest)?))
+}
+
+/// 値全体が1*DIGITであるかを確認し, 数値として返す
+pub fn parse_digits(value: &[u8]) -> Option<u64> {
+    if value.is_empty() {
+        return None;
+    }
+    value.iter().try_fold(0_u64, |acc, &c| {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        acc.checked_mul(10)?.checked_add(u64::from(c - b'0'))
+    })
+}
+
+/// 転送コーディング
+///
+/// transfer-coding = token *( OWS ";" OWS transfer-parameter )
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9112#name-transfer-codings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferCoding {
+    Chunked,
+    Compress,
+    Deflate,
+    Gzip,
+    /// サーバーが解釈できないコーディング
+    Extension,
+}
+
+impl TransferCoding {
+    fn from_name(name: &[u8]) -> Self {
+        if name.eq_ignore_ascii_case(b"chunked") {
+            TransferCoding::Chunked
+        } else if name.eq_ignore_ascii_case(b"compress") || name.eq_ignore_ascii_case(b"x-compress")
+        {
+            TransferCoding::Compress
+        } else if name.eq_ignore_ascii_case(b"deflate") {
+            TransferCoding::Deflate
+        } else if name.eq_ignore_ascii_case(b"gzip") || name.eq_ignore_ascii_case(b"x-gzip") {
+            TransferCoding::Gzip
+        } else {
+            TransferCoding::Extension
+        }
+    }
+}
+
+/// Transfer-Encodingの値を読み込み, 適用された順にcodingsへ追加する。
+/// 構文が正しくない場合はfalseを返す。
+/// 空の値は, Content-Lengthと組み合わせた場合にTransfer-Encodingが無いものとして
+/// 扱われてしまうので, コーディングが1つも無い場合も不正とする。
+///
+/// Transfer-Encoding = #transfer-coding
+pub fn parse_transfer_encoding(value: &[u8], codings: &mut Vec<TransferCoding>) -> bool {
+    let mut empty = true;
+    for element in list_elements(value) {
+        let (name, parameters) = match split_token(element) {
+            Some(split) => split,
+            None => return false,
+        };
+        if parse_parameters(parameters).is_none() {
+            return false;
+        }
+        codings.push(TransferCoding::from_name(name));
+        empty = false;
+    }
+    !empty
+}
+
+/// TEの値を読み込み, クライアントがtrailer fieldsを受け入れるかどうかを返す。
+/// 構文が正しくない場合はNoneを返す。
+///
+/// TE = #t-codings
+/// t-codings = "trailers" / ( transfer-coding [ weight ] )
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-te
+pub fn parse_te(value: &[u8]) -> Option<bool> {
+    let mut trailers = false;
+    for element in list_elements(value) {
+        let (name, parameters) = split_token(element)?;
+        parse_parameters(parameters)?;
+        if name.eq_ignore_ascii_case(b"trailers") {
+            trailers = true;
+        }
+    }
+    Some(trailers)
+}
+
+/// Connectionのconnection-optionを順に返す。tokenでない要素を含む場合はNoneを返す。
+///
+/// Connection = #connection-option
+/// connection-option = token
+pub fn parse_connection_options(value: &[u8]) -> Option<Vec<&[u8]>> {
+    list_elements(value)
+        .map(|element| match split_token(element) {
+            Some((option, b"")) => Some(option),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Expectで要求された動作
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-expect
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expectation {
+    /// 100-continue
+    Continue,
+    /// 100-continue以外の期待。サーバーは417 Expectation Failedで応答できる
+    Unsupported,
+}
+
+/// Expectの値を読み込む。構文が正しくない場合はNoneを返す。
+///
+/// Expect = #expectation
+/// expectation = token [ "=" ( token / quoted-string ) parameters ]
+pub fn parse_expect(value: &[u8]) -> Option<Expectation> {
+    let mut expectation = Expectation::Continue;
+    let mut empty = true;
+    for element in list_elements(value) {
+        let (name, rest) = split_token(element)?;
+        if !rest.is_empty() {
+            // "="以降はparameterと同じ構文なので, ";"を補って検証する
+            let mut parameter = b";x".to_vec();
+            parameter.extend_from_slice(rest);
+            parse_parameters(&parameter)?;
+        }
+        if !(name.eq_ignore_ascii_case(b"100-continue") && rest.is_empty()) {
+            expectation = Expectation::Unsupported;
+        }
+        empty = false;
+    }
+    if empty {
+        return None;
+    }
+    Some(expectation)
+}
+
+/// Upgradeの値を検証する。
+///
+/// Upgrade = #protocol
+/// protocol = protocol-name ["/" protocol-version]
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-upgrade
+pub fn is_valid_upgrade(value: &[u8]) -> bool {
+    let mut empty = true;
+    for element in list_elements(value) {
+        let (_, rest) = match split_token(element) {
+            Some(split) => split,
+            None => return false,
+        };
+        if !rest.is_empty() {
+            match rest.strip_prefix(b"/").and_then(split_token) {
+                Some((_, b"")) => {}
+                _ => return false,
+            }
+        }
+        empty = false;
+    }
+    !empty
+}
+
+/// Content-Typeで指定されたメディアタイプ。
+/// 各位置はバッファ全体での位置を表す。
+///
+/// media-type = type "/" subtype parameters
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-media-type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MediaType {
+    pub type_start: usize,
+    pub type_end: usize,
+    pub subtype_end: usize,
+    pub parameters_end: usize,
+}
+
+impl MediaType {
+    /// value_startから始まるvalueをメディアタイプとして読み込む。
+    /// 構文が正しくない場合はNoneを返す。
+    pub fn parse(value: &[u8], value_start: usize) -> Option<MediaType> {
+        let leading = value.len() - trim_start(value).len();
+        let media_type = trim(value);
+        let (_, after_type) = split_token(media_type)?;
+        let type_len = media_type.len() - after_type.len();
+        let (_, after_subtype) = split_token(after_type.strip_prefix(b"/")?)?;
+        let subtype_end = media_type.len() - after_subtype.len();
+        parse_parameters(after_subtype)?;
+        let start = value_start + leading;
+        Some(MediaType {
+            type_start: start,
+            type_end: start + type_len,
+            subtype_end: start + subtype_end,
+            parameters_end: start + media_type.len(),
+        })
+    }
+
+    /// "text/html"のようなtypeとsubtypeの組を返す。
+    /// typeとsubtypeは大文字・小文字を区別しないので, 比較にはeq_ignore_ascii_caseを使う。
+    pub fn essence<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.type_start..self.subtype_end]
+    }
+
+    pub fn media_type<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.type_start..self.type_end]
+    }
+
+    pub fn subtype<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.type_end + 1..self.subtype_end]
+    }
+
+    /// 名前が一致するparameterの値を返す。名前は大文字・小文字を区別しない。
+    pub fn parameter<'a, T: AsRef<[u8]> + ?Sized>(
+        &self,
+        buffer: &'a T,
+        name: &str,
+    ) -> Option<Cow<'a, [u8]>> {
+        let parameters = &buffer.as_ref()[self.subtype_end..self.parameters_end];
+        parse_parameters(parameters)?
+            .into_iter()
+            .find(|(parameter, _)| parameter.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_list_elements() {
+        let elements: Vec<_> = list_elements(b" a , ,b;q=\"x,y\",, c ").collect();
+        assert_eq!(elements, [&b"a"[..], b"b;q=\"x,y\"", b"c"]);
+        assert_eq!(list_elements(b" , ,").count(), 0);
+    }
+
+    #[test]
+    fn parse_parameters_with_quoted_string() {
+        let parameters = parse_parameters(b" ; charset=utf-8;;  name=\"a\\\"b c\"").unwrap();
+        assert_eq!(parameters.len(), 2);
+        assert_eq!(parameters[0].0, b"charset");
+        assert_eq!(&parameters[0].1[..], b"utf-8");
+        assert_eq!(parameters[1].0, b"name");
+        assert_eq!(&parameters[1].1[..], b"a\"b c");
+
+        assert!(parse_parameters(b"charset").is_none());
+        assert!(parse_parameters(b"; charset = utf-8").is_none());
+        assert!(parse_parameters(b"; name=\"unterminated").is_none());
+        assert!(parse_parameters(b"x; charset=utf-8").is_none());
+    }
+
+    #[test]
+    fn parse_token_and_its_parameters() {
+        let (token, parameters) =
+            parse_token_with_parameters(b" form-data; name=\"file\"; filename=a.txt").unwrap();
+        assert_eq!(token, b"form-data");
+        assert_eq!(parameters.len(), 2);
+        assert_eq!(&parameters[1].1[..], b"a.txt");
+        assert!(parse_token_with_parameters(b"; name=x").is_none());
+    }
+
+    #[test]
+    fn parse_digits_rejects_non_digits_and_overflow() {
+        assert_eq!(parse_digits(b"0"), Some(0));
+        assert_eq!(parse_digits(b"1234"), Some(1234));
+        assert_eq!(parse_digits(b""), None);
+        assert_eq!(parse_digits(b"+1"), None);
+        assert_eq!(parse_digits(b"1 2"), None);
+        assert_eq!(parse_digits(b"18446744073709551616"), None);
+    }
+
+    #[test]
+    fn parse_transfer_codings_in_order() {
+        let mut codings = Vec::new();
+        assert!(parse_transfer_encoding(b"gzip, Chunked", &mut codings));
+        assert!(parse_transfer_encoding(b"foo;bar=1", &mut codings));
+        assert_eq!(
+            codings,
+            [
+                TransferCoding::Gzip,
+                TransferCoding::Chunked,
+                TransferCoding::Extension
+            ]
+        );
+        assert!(parse_transfer_encoding(b"chunked;", &mut Vec::new()));
+        assert!(!parse_transfer_encoding(b"\"chunked\"", &mut Vec::new()));
+        assert!(!parse_transfer_encoding(b"chunked x", &mut Vec::new()));
+        assert!(!parse_transfer_encoding(b"", &mut Vec::new()));
+        assert!(!parse_transfer_encoding(b" , ,", &mut Vec::new()));
+    }
+
+    #[test]
+    fn parse_te_trailers() {
+        assert_eq!(parse_te(b"trailers, deflate;q=0.5"), Some(true));
+        assert_eq!(parse_te(b"gzip"), Some(false));
+        assert_eq!(parse_te(b""), Some(false));
+        assert_eq!(parse_te(b"gzip;q"), None);
+    }
+
+    #[test]
+    fn parse_connection_tokens() {
+        assert_eq!(
+            parse_connection_options(b"keep-alive, Upgrade").unwrap(),
+            [&b"keep-alive"[..], b"Upgrade"]
+        );
+        assert!(parse_connection_options(b"close x").is_none());
+        assert!(parse_connection_options(b"\"close\"").is_none());
+    }
+
+    #[test]
+    fn parse_expectations() {
+        assert_eq!(parse_expect(b"100-Continue"), Some(Expectation::Continue));
+        assert_eq!(parse_expect(b"foo=bar"), Some(Expectation::Unsupported));
+        assert_eq!(
+            parse_expect(b"100-continue, foo"),
+            Some(Expectation::Unsupported)
+        );
+        assert_eq!(parse_expect(b""), None);
+        assert_eq!(parse_expect(b"@"), None);
+    }
+
+    #[test]
+    fn validate_upgrade() {
+        assert!(is_valid_upgrade(b"websocket"));
+        assert!(is_valid_upgrade(b"HTTP/2.0, SHTTP/1.3, IRC/6.9"));
+        assert!(!is_valid_upgrade(b""));
+        assert!(!is_valid_upgrade(b"HTTP/"));
+        assert!(!is_valid_upgrade(b"HTTP/2 x"));
+    }
+
+    #[test]
+    fn parse_media_type() {
+        let buf = b"xx text/html ; Charset=\"UTF-8\" ";
+        let media_type = MediaType::parse(&buf[2..], 2).unwrap();
+        assert_eq!(media_type.essence(buf), b"text/html");
+        assert_eq!(media_type.media_type(buf), b"text");
+        assert_eq!(media_type.subtype(buf), b"html");
+        assert_eq!(&media_type.parameter(buf, "charset").unwrap()[..], b"UTF-8");
+        assert!(media_type.parameter(buf, "boundary").is_none());
+
+        assert!(MediaType::parse(b"text", 0).is_none());
+        assert!(MediaType::parse(b"text/", 0).is_none());
+        assert!(MediaType::parse(b"text/html; charset", 0).is_none());
+    }
+}
@@ -1,50 +1,398 @@
+use std::borrow::Cow;
+use std::str::Utf8Error;
 
+use super::header_name::KnownHeader;
+use super::header_value::{Expectation, MediaType, TransferCoding};
+use super::uri::{normalize_path, percent_decode, Host, PathMode, QueryPairs};
+
+/// リクエストラインで指定されたHTTPのバージョン
+///
+/// HTTP-version = HTTP-name "/" DIGIT "." DIGIT
+///
+/// Reference:
+/// https://www.rfc-editor.org/rfc/rfc9112#name-http-version
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HttpVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl HttpVersion {
+    pub const HTTP_1_0: HttpVersion = HttpVersion { major: 1, minor: 0 };
+    pub const HTTP_1_1: HttpVersion = HttpVersion { major: 1, minor: 1 };
+
+    pub fn new(major: u8, minor: u8) -> Self {
+        HttpVersion { major, minor }
+    }
+
+    /// このサーバーが応答できるメジャーバージョンかどうか。
+    /// マイナーバージョンが異なっても同じメジャーバージョンであれば互換性がある。
+    pub fn is_supported(&self) -> bool {
+        self.major == 1
+    }
+
+    /// Connectionヘッダーによる指定が無い場合に接続を維持するかどうか。
+    /// HTTP/1.1以降は持続的接続がデフォルトであり、HTTP/1.0はcloseがデフォルトである。
+    pub fn keep_alive_by_default(&self) -> bool {
+        *self >= HttpVersion::HTTP_1_1
+    }
+}
+
+/// リクエストメソッド
+/// RFC9110で定義されているメソッド以外はExtensionとして扱う。
+/// メソッド名は大文字・小文字を区別する。
+///
+/// method = token
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-methods
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method<'a> {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Connect,
+    Trace,
+    Extension(&'a [u8]),
+}
+
+impl<'a> Method<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Self {
+        match bytes {
+            b"GET" => Method::Get,
+            b"HEAD" => Method::Head,
+            b"POST" => Method::Post,
+            b"PUT" => Method::Put,
+            b"DELETE" => Method::Delete,
+            b"OPTIONS" => Method::Options,
+            b"PATCH" => Method::Patch,
+            b"CONNECT" => Method::Connect,
+            b"TRACE" => Method::Trace,
+            _ => Method::Extension(bytes),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        match self {
+            Method::Get => b"GET",
+            Method::Head => b"HEAD",
+            Method::Post => b"POST",
+            Method::Put => b"PUT",
+            Method::Delete => b"DELETE",
+            Method::Options => b"OPTIONS",
+            Method::Patch => b"PATCH",
+            Method::Connect => b"CONNECT",
+            Method::Trace => b"TRACE",
+            Method::Extension(bytes) => bytes,
+        }
+    }
+}
+
+impl std::fmt::Display for Method<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // パース時にtcharであることを検証しているのでASCIIとして表示できる
+        f.write_str(&String::from_utf8_lossy(self.as_bytes()))
+    }
+}
+
+/// request-targetの形式
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9112#name-request-target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// absolute-path [ "?" query ] (例: /index.html?q=1)
+    Origin,
+    /// absolute-URI (例: http://example.com/index.html)
+    Absolute,
+    /// uri-host ":" port (例: example.com:443) CONNECTでのみ使われる
+    Authority,
+    /// "*" OPTIONSでのみ使われる
+    Asterisk,
+}
+
+#[derive(Clone, Debug)]
 pub struct HTTPHeader {
+    /// リクエストの開始位置。リクエストラインの前の空行もここから数える
+    pub request_start: usize,
     pub method_start: usize,
     pub method_end: usize,
+    pub target: RequestTarget,
+    pub target_start: usize,
+    pub target_end: usize,
+    pub scheme_start: usize,
+    pub scheme_end: usize,
+    pub authority_start: usize,
+    pub authority_end: usize,
     pub path_start: usize,
     pub path_end: usize,
+    pub has_query: bool,
+    pub query_start: usize,
+    pub query_end: usize,
     pub protocol_start: usize,
     pub protocol_end: usize,
+    pub version: HttpVersion,
+    /// ヘッダーセクションの開始位置
+    pub header_start: usize,
+
+    /// Connectionヘッダーで明示的に指定された接続の維持/切断
+    pub keep_alive: Option<bool>,
+    /// Hostヘッダー
+    pub host: Option<Field>,
+    /// リクエストの対象となるホスト。
+    /// absolute-formとauthority-formではrequest-targetから, それ以外はHostヘッダーから求める。
+    pub target_host: Option<Host>,
+    /// Content-Lengthで指定されたボディの長さ
+    pub content_length: Option<u64>,
+    /// Transfer-Encodingで適用された順の転送コーディング
+    pub transfer_encoding: Vec<TransferCoding>,
+    /// Expectで要求された動作
+    pub expect: Option<Expectation>,
+    /// Upgradeヘッダー
+    pub upgrade: Option<Field>,
+    /// TEでtrailer fieldsを受け入れることが示された
+    pub te_trailers: bool,
+    /// Content-Typeで指定されたメディアタイプ
+    pub content_type: Option<MediaType>,
 
     pub field_size: usize,
+    /// 受信した順のヘッダー。obs-foldの行は直前のヘッダーに含まれる
     pub fields: Vec<Field>,
+    /// KnownHeaderごとに最初に現れたヘッダーのfieldsでの位置
+    known_index: [Option<usize>; KnownHeader::COUNT],
+    /// KnownHeaderごとに最後に現れたヘッダーのfieldsでの位置
+    known_last: [Option<usize>; KnownHeader::COUNT],
+    /// fieldsと同じ並びで, 同じKnownHeaderの次のヘッダーの位置を持つ
+    known_next: Vec<Option<usize>>,
 }
 
 impl HTTPHeader {
     pub fn new() -> Self {
         HTTPHeader {
+            request_start: 0,
             method_start: 0,
             method_end: 0,
+            target: RequestTarget::Origin,
+            target_start: 0,
+            target_end: 0,
+            scheme_start: 0,
+            scheme_end: 0,
+            authority_start: 0,
+            authority_end: 0,
             path_start: 0,
             path_end: 0,
+            has_query: false,
+            query_start: 0,
+            query_end: 0,
             protocol_start: 0,
             protocol_end: 0,
+            version: HttpVersion::HTTP_1_1,
+            header_start: 0,
+            keep_alive: None,
+            host: None,
+            target_host: None,
+            content_length: None,
+            transfer_encoding: Vec::new(),
+            expect: None,
+            upgrade: None,
+            te_trailers: false,
+            content_type: None,
             field_size: 0,
             fields: Vec::new(),
+            known_index: [None; KnownHeader::COUNT],
+            known_last: [None; KnownHeader::COUNT],
+            known_next: Vec::new(),
+        }
+    }
+
+    pub fn method<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Method<'a> {
+        Method::from_bytes(&buffer.as_ref()[self.method_start..self.method_end])
+    }
+
+    /// request-target全体を返す
+    pub fn target<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.target_start..self.target_end]
+    }
+
+    /// absolute-formのschemeを返す
+    pub fn scheme<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Option<&'a [u8]> {
+        if self.target != RequestTarget::Absolute {
+            return None;
+        }
+        Some(&buffer.as_ref()[self.scheme_start..self.scheme_end])
+    }
+
+    /// リクエストの対象となるauthorityを返す。
+    /// absolute-formまたはauthority-formの場合はHostヘッダーを無視し、
+    /// request-targetに含まれるauthorityを優先する。
+    ///
+    /// References:
+    /// https://www.rfc-editor.org/rfc/rfc9112#name-reconstructing-the-target-u
+    pub fn authority<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Option<&'a [u8]> {
+        match self.target {
+            RequestTarget::Absolute | RequestTarget::Authority => {
+                Some(&buffer.as_ref()[self.authority_start..self.authority_end])
+            }
+            RequestTarget::Origin | RequestTarget::Asterisk => {
+                self.host.as_ref().map(|field| field.value_bytes(buffer))
+            }
+        }
+    }
+
+    /// 仮想ホストの選択に使う, 正規化したホスト名を返す
+    pub fn normalized_host<'a, T: AsRef<[u8]> + ?Sized>(
+        &self,
+        buffer: &'a T,
+    ) -> Option<Cow<'a, [u8]>> {
+        self.target_host.map(|host| host.normalized(buffer))
+    }
+
+    /// pct-encodedをデコードする前のpathを返す
+    pub fn path<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.path_start..self.path_end]
+    }
+
+    /// pct-encodedをデコードしたpathを返す。
+    /// absolute-formでpathが空の場合は"/"として扱う。
+    pub fn decoded_path<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Cow<'a, [u8]> {
+        let path = &buffer.as_ref()[self.path_start..self.path_end];
+        if path.is_empty() {
+            return Cow::Borrowed(b"/");
         }
+        percent_decode(path)
     }
 
-    pub fn method<'a, T: AsRef<[u8]>>(&self, buffer: &'a T) -> &'a str {
-        std::str::from_utf8(&buffer.as_ref()[self.method_start..self.method_end]).unwrap()
+    /// デコードしたpathからdot-segmentと重複した"/"を取り除いたものを返す。
+    /// ハンドラーがファイルなどを参照する場合はこのpathを使う。
+    /// modeがStrictでエンコードされた"/"またはNULを含む場合はNoneを返す。
+    pub fn normalized_path<'a, T: AsRef<[u8]> + ?Sized>(
+        &self,
+        buffer: &'a T,
+        mode: PathMode,
+    ) -> Option<Cow<'a, [u8]>> {
+        let path = &buffer.as_ref()[self.path_start..self.path_end];
+        match self.target {
+            RequestTarget::Asterisk => Some(Cow::Borrowed(path)),
+            _ if path.is_empty() => Some(Cow::Borrowed(b"/")),
+            _ => normalize_path(path, mode),
+        }
     }
 
-    pub fn path<'a, T: AsRef<[u8]>>(&self, buffer: &'a T) -> &'a str {
-        std::str::from_utf8(&buffer.as_ref()[self.path_start..self.path_end]).unwrap()
+    /// queryをapplication/x-www-form-urlencodedとして名前と値の組に分解する。
+    /// queryが無い場合は何も返さない。
+    pub fn query_pairs<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> QueryPairs<'a> {
+        if !self.has_query {
+            return QueryPairs::new(&[]);
+        }
+        QueryPairs::new(&buffer.as_ref()[self.query_start..self.query_end])
     }
 
-    pub fn protocol<'a, T: AsRef<[u8]>>(&self, buffer: &'a T) -> &'a str {
-        std::str::from_utf8(&buffer.as_ref()[self.protocol_start..self.protocol_end]).unwrap()
+    /// "?"以降のqueryを返す。"?"が無い場合はNoneを返す
+    pub fn query<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Option<&'a [u8]> {
+        if !self.has_query {
+            return None;
+        }
+        Some(&buffer.as_ref()[self.query_start..self.query_end])
+    }
+
+    pub fn protocol<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.protocol_start..self.protocol_end]
+    }
+
+    /// レスポンス送信後に接続を維持するかどうか
+    pub fn is_keep_alive(&self) -> bool {
+        self.keep_alive
+            .unwrap_or_else(|| self.version.keep_alive_by_default())
+    }
+
+    /// ボディがchunkedで転送されるかどうか。chunkedは最後に適用されたコーディングでなければならない。
+    pub fn is_chunked(&self) -> bool {
+        self.transfer_encoding.last() == Some(&TransferCoding::Chunked)
     }
 
     pub fn add_field(&mut self, field: Field) {
+        let position = self.fields.len();
+        if let Some(known) = field.known {
+            // 同じKnownHeaderのヘッダーを受信した順につなげておく
+            match self.known_last[known.index()] {
+                Some(last) => self.known_next[last] = Some(position),
+                None => self.known_index[known.index()] = Some(position),
+            }
+            self.known_last[known.index()] = Some(position);
+        }
         self.fields.push(field);
+        self.known_next.push(None);
         self.field_size += 1;
     }
+
+    /// KnownHeaderのうち最初に現れたものを返す。一覧を走査せずに参照できる。
+    pub fn known(&self, header: KnownHeader) -> Option<&Field> {
+        self.known_index[header.index()].map(|i| &self.fields[i])
+    }
+
+    /// KnownHeaderに一致するヘッダーを受信した順に全て返す。一覧を走査せずに参照できる。
+    pub fn known_all(&self, header: KnownHeader) -> impl Iterator<Item = &Field> {
+        std::iter::successors(self.known_index[header.index()], move |&i| {
+            self.known_next[i]
+        })
+        .map(move |i| &self.fields[i])
+    }
+
+    /// nameに一致するヘッダーのうち最初に現れたものを返す。
+    /// nameは大文字・小文字を区別しない。
+    pub fn get<'a, T: AsRef<[u8]> + ?Sized>(&'a self, name: &str, buffer: &T) -> Option<&'a Field> {
+        if let Some(known) = KnownHeader::from_name(name.as_bytes()) {
+            return self.known(known);
+        }
+        self.fields.iter().find(|field| {
+            field
+                .name_bytes(buffer)
+                .eq_ignore_ascii_case(name.as_bytes())
+        })
+    }
+
+    /// nameに一致するヘッダーを受信した順に全て返す。
+    /// nameは大文字・小文字を区別しない。KnownHeaderであれば一覧を走査しない。
+    pub fn get_all<'a, T: AsRef<[u8]> + ?Sized>(
+        &'a self,
+        name: &'a str,
+        buffer: &'a T,
+    ) -> impl Iterator<Item = &'a Field> {
+        let known = KnownHeader::from_name(name.as_bytes());
+        let unknown = match known {
+            Some(_) => &self.fields[..0],
+            None => &self.fields[..],
+        };
+        known
+            .into_iter()
+            .flat_map(move |known| self.known_all(known))
+            .chain(unknown.iter().filter(move |field| {
+                field
+                    .name_bytes(buffer)
+                    .eq_ignore_ascii_case(name.as_bytes())
+            }))
+    }
+}
+
+impl Default for HTTPHeader {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct Field {
     pub is_separator: bool,
+    /// この行が直前のヘッダーの続き(obs-fold)である
+    pub is_obs_fold: bool,
+    /// 値の途中にobs-foldを含む。replace_obs_foldで改行をSPに置き換える
+    pub has_obs_fold: bool,
+    /// field-nameがKnownHeaderのいずれかであればその種類
+    pub known: Option<KnownHeader>,
     pub name_start: usize,
     pub name_end: usize,
     pub value_start: usize,
@@ -55,6 +403,9 @@ impl Field {
     pub fn new() -> Self {
         Field {
             is_separator: false,
+            is_obs_fold: false,
+            has_obs_fold: false,
+            known: None,
             name_start: 0,
             name_end: 0,
             value_start: 0,
@@ -62,12 +413,35 @@ impl Field {
         }
     }
 
-    pub fn name<'a, T: AsRef<[u8]>>(&self, buffer: &'a T) -> &'a str {
-        std::str::from_utf8(&buffer.as_ref()[self.name_start..self.name_end]).unwrap()
+    /// field-nameを文字列として返す。
+    /// field-nameはtokenなので, パースに成功していればUTF-8として解釈できる。
+    pub fn name<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Result<&'a str, Utf8Error> {
+        std::str::from_utf8(self.name_bytes(buffer))
     }
 
-    pub fn value<'a, T: AsRef<[u8]>>(&self, buffer: &'a T) -> &'a str {
-        std::str::from_utf8(&buffer.as_ref()[self.value_start..self.value_end]).unwrap()
+    pub fn name_bytes<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.name_start..self.name_end]
+    }
+
+    /// field-valueを文字列として返す。
+    /// obs-textを含む場合はUTF-8として解釈できないことがあるので, その場合はエラーを返す。
+    pub fn value<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Result<&'a str, Utf8Error> {
+        std::str::from_utf8(self.value_bytes(buffer))
+    }
+
+    /// field-valueを文字列として返す。UTF-8として解釈できないバイトは置換文字に置き換える。
+    pub fn value_lossy<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Cow<'a, str> {
+        String::from_utf8_lossy(self.value_bytes(buffer))
+    }
+
+    pub fn value_bytes<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.value_start..self.value_end]
+    }
+}
+
+impl Default for Field {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -77,4 +451,6 @@ pub enum ParseResult<T> {
     Ok(T),
     Complete,
     Error,
+    /// ParserConfigで指定したサイズの上限を超えた
+    TooLarge,
 }
@@ -0,0 +1,437 @@
+//! multipart/form-dataのボディのパース
+//!
+//! ボディは分割して届くので, 入力を受け取るたびにパートのヘッダーと内容を順に通知する。
+//! パートの内容はboundaryの一部かもしれない末尾の数バイトを除いてすぐに通知するので,
+//! ファイル全体をメモリに保持することはない。
+//!
+//! multipart-body = [preamble CRLF] dash-boundary transport-padding CRLF
+//!                  body-part *encapsulation close-delimiter transport-padding [CRLF epilogue]
+//! encapsulation = delimiter transport-padding CRLF body-part
+//! delimiter = CRLF dash-boundary
+//! dash-boundary = "--" boundary
+//! close-delimiter = delimiter "--"
+//!
+//! References:
+//! https://www.rfc-editor.org/rfc/rfc2046#section-5.1.1
+//! https://www.rfc-editor.org/rfc/rfc7578
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use memchr::memmem;
+
+use super::config::ParserConfig;
+use super::header_value::parse_token_with_parameters;
+use super::http_interface::{Field, HTTPHeader, ParseResult};
+use super::parse_request_header::{parse_http_request_header, RequestHeaderState};
+
+pub enum MultipartEvent<'a> {
+    /// パートのヘッダーを読み終えた
+    PartStart(&'a Part),
+    /// パートの内容の一部
+    Data(&'a [u8]),
+    /// パートの内容を読み終えた
+    PartEnd,
+}
+
+pub enum MultipartResult {
+    /// 入力が足りない。続きのボディを渡して再度呼び出す
+    Again,
+    /// close-delimiterを読み終えた
+    Complete,
+    Error,
+    /// パートのヘッダーがconfig.max_header_section_sizeを超えた
+    TooLarge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MultipartState {
+    /// 最初のboundaryの前
+    Preamble,
+    /// boundaryの直後
+    AfterBoundary,
+    Header,
+    Data,
+    /// close-delimiterの後
+    Epilogue,
+}
+
+/// multipartの1つのパート。
+/// ヘッダーはリクエストのヘッダーと同じくFieldとして, パート自身のバッファ上の位置を保持する。
+#[derive(Clone, Debug)]
+pub struct Part {
+    buf: Vec<u8>,
+    header: HTTPHeader,
+    field: Field,
+    state: RequestHeaderState,
+    parsed: usize,
+}
+
+impl Part {
+    fn new() -> Self {
+        Part {
+            buf: Vec::new(),
+            header: HTTPHeader::new(),
+            field: Field::new(),
+            state: RequestHeaderState::Start,
+            parsed: 0,
+        }
+    }
+
+    /// Fieldが位置を指しているバッファ
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// パートのヘッダーを受信した順に返す
+    pub fn fields(&self) -> &[Field] {
+        &self.header.fields
+    }
+
+    /// nameに一致するヘッダーのうち最初に現れたものを返す
+    pub fn get(&self, name: &str) -> Option<&Field> {
+        self.header.get(name, &self.buf)
+    }
+
+    /// Content-Dispositionのparameterを返す
+    pub fn disposition_parameter(&self, name: &str) -> Option<Cow<'_, [u8]>> {
+        let field = self.get("Content-Disposition")?;
+        let (_, parameters) = parse_token_with_parameters(field.value_bytes(&self.buf))?;
+        parameters
+            .into_iter()
+            .find(|(parameter, _)| parameter.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| value)
+    }
+
+    /// フォームのフィールド名
+    pub fn name(&self) -> Option<Cow<'_, [u8]>> {
+        self.disposition_parameter("name")
+    }
+
+    /// ファイルを送信する場合の元のファイル名
+    pub fn filename(&self) -> Option<Cow<'_, [u8]>> {
+        self.disposition_parameter("filename")
+    }
+
+    pub fn content_type(&self) -> Option<Cow<'_, str>> {
+        self.get("Content-Type")
+            .map(|field| field.value_lossy(&self.buf))
+    }
+}
+
+/// multipartのボディを読み込むステートマシン
+#[derive(Clone, Debug)]
+pub struct MultipartParser {
+    /// CRLF "--" boundary
+    delimiter: Vec<u8>,
+    state: MultipartState,
+    /// まだ処理していない入力
+    pending: Vec<u8>,
+    part: Part,
+}
+
+impl MultipartParser {
+    /// boundaryは1文字以上70文字以下でなければならない
+    pub fn new(boundary: &[u8]) -> Option<Self> {
+        if boundary.is_empty() || boundary.len() > 70 {
+            return None;
+        }
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary);
+        Some(MultipartParser {
+            delimiter,
+            state: MultipartState::Preamble,
+            // 先頭のdash-boundaryもdelimiterとして探せるように, CRLFを補っておく
+            pending: b"\r\n".to_vec(),
+            part: Part::new(),
+        })
+    }
+
+    /// Content-Typeがmultipart/form-dataの場合に, boundaryパラメーターからパーサーを作る
+    pub fn from_header<T: AsRef<[u8]>>(header: &HTTPHeader, buffer: &T) -> Option<Self> {
+        let content_type = header.content_type?;
+        if !content_type
+            .essence(buffer)
+            .eq_ignore_ascii_case(b"multipart/form-data")
+        {
+            return None;
+        }
+        let boundary = content_type.parameter(buffer, "boundary")?;
+        MultipartParser::new(&boundary)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state == MultipartState::Epilogue
+    }
+
+    /// 続きのボディを読み込み, パートの開始・内容・終了をhandlerに通知する
+    pub fn feed(
+        &mut self,
+        input: &[u8],
+        config: &ParserConfig,
+        handler: &mut dyn FnMut(MultipartEvent),
+    ) -> MultipartResult {
+        if self.state == MultipartState::Epilogue {
+            return MultipartResult::Complete;
+        }
+        self.pending.extend_from_slice(input);
+        loop {
+            let result = match self.state {
+                MultipartState::Preamble => self.parse_preamble(),
+                MultipartState::AfterBoundary => self.parse_after_boundary(),
+                MultipartState::Header => self.parse_header(config, handler),
+                MultipartState::Data => self.parse_data(handler),
+                MultipartState::Epilogue => ParseResult::Complete,
+            };
+            match result {
+                ParseResult::Ok(state) => self.state = state,
+                ParseResult::Again(state) => {
+                    self.state = state;
+                    return MultipartResult::Again;
+                }
+                ParseResult::Complete => {
+                    // epilogueは読み捨てる
+                    self.state = MultipartState::Epilogue;
+                    self.pending.clear();
+                    return MultipartResult::Complete;
+                }
+                ParseResult::Error => return MultipartResult::Error,
+                ParseResult::TooLarge => return MultipartResult::TooLarge,
+            }
+        }
+    }
+
+    /// 最初のboundaryまでのpreambleを読み捨てる
+    fn parse_preamble(&mut self) -> ParseResult<MultipartState> {
+        match memmem::find(&self.pending, &self.delimiter) {
+            Some(n) => {
+                self.pending.drain(..n + self.delimiter.len());
+                ParseResult::Ok(MultipartState::AfterBoundary)
+            }
+            None => {
+                let keep = (self.delimiter.len() - 1).min(self.pending.len());
+                self.pending.drain(..self.pending.len() - keep);
+                ParseResult::Again(MultipartState::Preamble)
+            }
+        }
+    }
+
+    /// boundaryの後が"--"であれば終わり, そうでなければtransport-paddingとCRLFの後にパートが続く
+    ///
+    /// transport-padding = *LWSP-char
+    fn parse_after_boundary(&mut self) -> ParseResult<MultipartState> {
+        if self.pending.len() < 2 {
+            return ParseResult::Again(MultipartState::AfterBoundary);
+        }
+        if self.pending.starts_with(b"--") {
+            return ParseResult::Complete;
+        }
+        let padding = self
+            .pending
+            .iter()
+            .position(|&c| c != b' ' && c != b'\t')
+            .unwrap_or(self.pending.len());
+        if !self.pending[padding..].starts_with(b"\r\n") {
+            if self.pending.len() - padding < 2 && b"\r\n".starts_with(&self.pending[padding..]) {
+                return ParseResult::Again(MultipartState::AfterBoundary);
+            }
+            return ParseResult::Error;
+        }
+        self.pending.drain(..padding + 2);
+        self.part = Part::new();
+        ParseResult::Ok(MultipartState::Header)
+    }
+
+    /// パートのヘッダーをリクエストのヘッダーと同じようにパースする。
+    /// Fieldの位置が変わらないように, ヘッダーはパート自身のバッファに移してからパースする。
+    fn parse_header(
+        &mut self,
+        config: &ParserConfig,
+        handler: &mut dyn FnMut(MultipartEvent),
+    ) -> ParseResult<MultipartState> {
+        let part = &mut self.part;
+        part.buf.append(&mut self.pending);
+        let mut cursor = Cursor::new(&part.buf[..]);
+        cursor.set_position(part.parsed as u64);
+        loop {
+            match parse_http_request_header(
+                &mut cursor,
+                &mut part.header,
+                &mut part.field,
+                part.state.clone(),
+                config,
+            ) {
+                ParseResult::Complete if part.field.is_separator => break,
+                ParseResult::Complete => {
+                    part.field = Field::new();
+                    part.state = RequestHeaderState::Start;
+                }
+                ParseResult::Again(state) => {
+                    part.state = state;
+                    part.parsed = cursor.position() as usize;
+                    return ParseResult::Again(MultipartState::Header);
+                }
+                ParseResult::TooLarge => return ParseResult::TooLarge,
+                ParseResult::Ok(_) | ParseResult::Error => return ParseResult::Error,
+            }
+        }
+        let end = cursor.position() as usize;
+        self.pending = part.buf.split_off(end);
+        part.parsed = end;
+        handler(MultipartEvent::PartStart(part));
+        ParseResult::Ok(MultipartState::Data)
+    }
+
+    /// 次のdelimiterまでをパートの内容として通知する。
+    /// delimiterが見つからない場合も, delimiterの途中かもしれない末尾以外はすぐに通知する。
+    fn parse_data(
+        &mut self,
+        handler: &mut dyn FnMut(MultipartEvent),
+    ) -> ParseResult<MultipartState> {
+        match memmem::find(&self.pending, &self.delimiter) {
+            Some(n) => {
+                if n > 0 {
+                    handler(MultipartEvent::Data(&self.pending[..n]));
+                }
+                handler(MultipartEvent::PartEnd);
+                self.pending.drain(..n + self.delimiter.len());
+                ParseResult::Ok(MultipartState::AfterBoundary)
+            }
+            None => {
+                let keep = (self.delimiter.len() - 1).min(self.pending.len());
+                let n = self.pending.len() - keep;
+                if n > 0 {
+                    handler(MultipartEvent::Data(&self.pending[..n]));
+                    self.pending.drain(..n);
+                }
+                ParseResult::Again(MultipartState::Data)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct ParsedPart {
+        name: Vec<u8>,
+        filename: Option<Vec<u8>>,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    }
+
+    /// bodyをchunk_sizeずつ渡してパースする
+    fn parse_all(boundary: &[u8], body: &[u8], chunk_size: usize) -> Option<Vec<ParsedPart>> {
+        let mut parser = MultipartParser::new(boundary).unwrap();
+        let config = ParserConfig::default();
+        let mut parts: Vec<ParsedPart> = Vec::new();
+        for chunk in body.chunks(chunk_size) {
+            let result = parser.feed(chunk, &config, &mut |event| match event {
+                MultipartEvent::PartStart(part) => parts.push(ParsedPart {
+                    name: part.name().unwrap().into_owned(),
+                    filename: part.filename().map(|name| name.into_owned()),
+                    content_type: part.content_type().map(|value| value.into_owned()),
+                    content: Vec::new(),
+                }),
+                MultipartEvent::Data(data) => {
+                    parts.last_mut().unwrap().content.extend_from_slice(data)
+                }
+                MultipartEvent::PartEnd => {}
+            });
+            match result {
+                MultipartResult::Again => {}
+                MultipartResult::Complete => return Some(parts),
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    const BODY: &[u8] = b"preamble\r\n\
+--AaB03x\r\n\
+Content-Disposition: form-data; name=\"submit-name\"\r\n\
+\r\n\
+Larry\r\n\
+--AaB03x  \r\n\
+Content-Disposition: form-data; name=\"files\"; filename=\"file1.txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+... contents of file1.txt ...\r\n--AaB0\r\n\
+--AaB03x--\r\n\
+epilogue";
+
+    #[test]
+    fn parse_multipart_form_data() {
+        let parts = parse_all(b"AaB03x", BODY, BODY.len()).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, b"submit-name");
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].content, b"Larry");
+        assert_eq!(parts[1].name, b"files");
+        assert_eq!(parts[1].filename.as_deref(), Some(&b"file1.txt"[..]));
+        assert_eq!(parts[1].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(parts[1].content, b"... contents of file1.txt ...\r\n--AaB0");
+    }
+
+    #[test]
+    fn parse_from_each_chunk_size() {
+        let expected = parse_all(b"AaB03x", BODY, BODY.len()).unwrap();
+        for chunk_size in 1..BODY.len() {
+            assert_eq!(parse_all(b"AaB03x", BODY, chunk_size).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn body_without_preamble() {
+        let body = b"--x\r\nContent-Disposition: form-data; name=a\r\n\r\n1\r\n--x--";
+        let parts = parse_all(b"x", body, 3).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].content, b"1");
+    }
+
+    #[test]
+    fn large_content_is_streamed() {
+        let mut body = b"--x\r\nContent-Disposition: form-data; name=a\r\n\r\n".to_vec();
+        body.resize(body.len() + 100_000, b'a');
+        body.extend_from_slice(b"\r\n--x--\r\n");
+
+        let mut parser = MultipartParser::new(b"x").unwrap();
+        let config = ParserConfig::default();
+        let mut received = 0;
+        for chunk in body.chunks(4096) {
+            parser.feed(chunk, &config, &mut |event| {
+                if let MultipartEvent::Data(data) = event {
+                    received += data.len();
+                }
+            });
+            // 未処理の入力はdelimiterの長さ程度しか残らない
+            assert!(parser.pending.len() < 4096 + parser.delimiter.len());
+        }
+        assert!(parser.is_complete());
+        assert_eq!(received, 100_000);
+    }
+
+    #[test]
+    fn invalid_multipart_should_failed() {
+        let body = b"--x\r\nContent-Disposition form-data\r\n\r\n1\r\n--x--";
+        assert!(parse_all(b"x", body, body.len()).is_none());
+        let body = b"--xjunk\r\n\r\n1\r\n--x--";
+        assert!(parse_all(b"x", body, body.len()).is_none());
+        assert!(MultipartParser::new(b"").is_none());
+        assert!(MultipartParser::new(&[b'a'; 71]).is_none());
+    }
+
+    #[test]
+    fn from_content_type_header() {
+        let mut header = HTTPHeader::new();
+        let buf = b"multipart/form-data; boundary=\"AaB03x\"";
+        header.content_type = crate::http::header_value::MediaType::parse(buf, 0);
+        let parser = MultipartParser::from_header(&header, buf).unwrap();
+        assert_eq!(parser.delimiter, b"\r\n--AaB03x");
+
+        let buf = b"text/plain; boundary=AaB03x";
+        header.content_type = crate::http::header_value::MediaType::parse(buf, 0);
+        assert!(MultipartParser::from_header(&header, buf).is_none());
+    }
+}
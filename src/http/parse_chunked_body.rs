@@ -0,0 +1,439 @@
+//! chunked transfer codingで送られたボディのデコード
+//!
+//! chunked-body = *chunk last-chunk trailer-section CRLF
+//! chunk = chunk-size [ chunk-ext ] CRLF chunk-data CRLF
+//! chunk-size = 1*HEXDIG
+//! last-chunk = 1*("0") [ chunk-ext ] CRLF
+//!
+//! References:
+//! https://www.rfc-editor.org/rfc/rfc9112#name-chunked-transfer-coding
+use std::io::Cursor;
+use std::ops::Range;
+
+use memchr::memchr2;
+
+use super::config::ParserConfig;
+use super::http_interface::{Field, HTTPHeader, ParseResult};
+use super::parse_request_header::{parse_http_request_header, RequestHeaderState};
+use super::parse_utility::{
+    advance, all_field_content, hex_value, read_byte, remaining, ReadResult,
+};
+
+#[derive(Clone, Debug)]
+pub enum ChunkedState {
+    Size,
+    /// chunk-sizeの後のchunk-ext
+    Extension,
+    /// chunk-sizeの行末のLF
+    SizeLf,
+    Data,
+    /// chunk-dataの後のCR
+    DataCr,
+    /// chunk-dataの後のLF
+    DataLf,
+    Trailer(RequestHeaderState),
+    End,
+}
+
+pub enum ChunkedResult {
+    /// buffer[range]がchunk-dataである。続きを読むために再度呼び出す
+    Data(Range<usize>),
+    /// 入力が足りない。追加のデータを読み込んでから再度呼び出す
+    Again,
+    /// last-chunkとtrailer sectionを読み終えた
+    Complete,
+    Error,
+    /// chunk-sizeがconfig.max_chunk_sizeを超えた, または行やtrailer sectionが長すぎる
+    TooLarge,
+}
+
+/// chunkedのボディを読み込むステートマシン。
+/// 読み込んだ位置を保持しているので, 入力が途中で途切れても続きからデコードできる。
+#[derive(Clone, Debug)]
+pub struct ChunkedDecoder {
+    pub state: ChunkedState,
+    /// 読み込み中のchunk-size
+    size: u64,
+    /// 読み込み中のchunkの残りのバイト数
+    remaining: u64,
+    /// chunk-sizeの行のうち読み込んだバイト数
+    line_size: usize,
+    /// trailer sectionのヘッダー
+    trailer: HTTPHeader,
+    field: Field,
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> Self {
+        ChunkedDecoder {
+            state: ChunkedState::Size,
+            size: 0,
+            remaining: 0,
+            line_size: 0,
+            trailer: HTTPHeader::new(),
+            field: Field::new(),
+        }
+    }
+
+    /// trailer sectionで送られたヘッダーを受信した順に返す
+    pub fn trailers(&self) -> &[Field] {
+        &self.trailer.fields
+    }
+
+    /// nameに一致するtrailer fieldのうち最初に現れたものを返す
+    pub fn trailer<'a, T: AsRef<[u8]>>(&'a self, name: &str, buffer: &T) -> Option<&'a Field> {
+        self.trailer.get(name, buffer)
+    }
+
+    /// cursorの位置からchunkedのボディをデコードする。
+    /// chunk-dataは1回の呼び出しにつき1つの範囲として返すので, CompleteかAgainになるまで繰り返し呼び出す。
+    pub fn decode<T: AsRef<[u8]>>(
+        &mut self,
+        cursor: &mut Cursor<T>,
+        config: &ParserConfig,
+    ) -> ChunkedResult {
+        loop {
+            let result = match self.state.clone() {
+                ChunkedState::Size => self.parse_size(cursor, config),
+                ChunkedState::Extension => self.parse_extension(cursor, config),
+                ChunkedState::SizeLf => self.parse_size_lf(cursor),
+                ChunkedState::Data => return self.parse_data(cursor),
+                ChunkedState::DataCr => self.parse_data_cr(cursor, config),
+                ChunkedState::DataLf => self.parse_data_lf(cursor),
+                ChunkedState::Trailer(state) => self.parse_trailer(cursor, state, config),
+                ChunkedState::End => ParseResult::Complete,
+            };
+            match result {
+                ParseResult::Ok(state) => self.state = state,
+                ParseResult::Again(state) => {
+                    self.state = state;
+                    return ChunkedResult::Again;
+                }
+                ParseResult::Complete => {
+                    self.state = ChunkedState::End;
+                    return ChunkedResult::Complete;
+                }
+                ParseResult::Error => return ChunkedResult::Error,
+                ParseResult::TooLarge => return ChunkedResult::TooLarge,
+            }
+        }
+    }
+
+    /// chunk-sizeを16進数として読み込む
+    fn parse_size<T: AsRef<[u8]>>(
+        &mut self,
+        cursor: &mut Cursor<T>,
+        config: &ParserConfig,
+    ) -> ParseResult<ChunkedState> {
+        loop {
+            let c = match read_byte(cursor) {
+                ReadResult::Ok(c) => c,
+                ReadResult::Again => return ParseResult::Again(ChunkedState::Size),
+                ReadResult::Err => return ParseResult::Error,
+            };
+            self.line_size += 1;
+            if let Some(digit) = hex_value(c) {
+                self.size = match self
+                    .size
+                    .checked_mul(16)
+                    .and_then(|size| size.checked_add(u64::from(digit)))
+                {
+                    Some(size) => size,
+                    None => return ParseResult::TooLarge,
+                };
+                if self.size > config.max_chunk_size
+                    || self.line_size > config.max_header_field_size
+                {
+                    return ParseResult::TooLarge;
+                }
+                continue;
+            }
+            // chunk-sizeは1桁以上必要
+            if self.line_size == 1 {
+                return ParseResult::Error;
+            }
+            return match c {
+                b';' | b' ' | b'\t' => ParseResult::Ok(ChunkedState::Extension),
+                b'\r' => ParseResult::Ok(ChunkedState::SizeLf),
+                b'\n' if config.allow_bare_lf => self.end_size_line(),
+                _ => ParseResult::Error,
+            };
+        }
+    }
+
+    /// chunk-extを読み飛ばす。
+    /// サーバーはchunk-extを解釈しないので, 行末まで使える文字であることだけを確認する。
+    ///
+    /// chunk-ext = *( BWS ";" BWS chunk-ext-name [ BWS "=" BWS chunk-ext-val ] )
+    fn parse_extension<T: AsRef<[u8]>>(
+        &mut self,
+        cursor: &mut Cursor<T>,
+        config: &ParserConfig,
+    ) -> ParseResult<ChunkedState> {
+        let rest = remaining(cursor);
+        let (extension, line_end) = match memchr2(b'\r', b'\n', rest) {
+            Some(n) => (&rest[..n], Some(rest[n])),
+            None => (rest, None),
+        };
+        if !all_field_content(extension) {
+            return ParseResult::Error;
+        }
+        self.line_size += extension.len();
+        if self.line_size > config.max_header_field_size {
+            return ParseResult::TooLarge;
+        }
+        match line_end {
+            Some(b'\r') => {
+                advance(cursor, extension.len() + 1);
+                ParseResult::Ok(ChunkedState::SizeLf)
+            }
+            Some(_) => {
+                if !config.allow_bare_lf {
+                    return ParseResult::Error;
+                }
+                advance(cursor, extension.len() + 1);
+                self.end_size_line()
+            }
+            None => {
+                advance(cursor, extension.len());
+                ParseResult::Again(ChunkedState::Extension)
+            }
+        }
+    }
+
+    fn parse_size_lf<T: AsRef<[u8]>>(
+        &mut self,
+        cursor: &mut Cursor<T>,
+    ) -> ParseResult<ChunkedState> {
+        match read_byte(cursor) {
+            ReadResult::Ok(b'\n') => self.end_size_line(),
+            ReadResult::Ok(_) => ParseResult::Error,
+            ReadResult::Again => ParseResult::Again(ChunkedState::SizeLf),
+            ReadResult::Err => ParseResult::Error,
+        }
+    }
+
+    /// chunk-sizeの行を読み終えたので, chunk-dataかtrailer sectionに進む
+    fn end_size_line(&mut self) -> ParseResult<ChunkedState> {
+        self.line_size = 0;
+        self.remaining = self.size;
+        self.size = 0;
+        if self.remaining == 0 {
+            return ParseResult::Ok(ChunkedState::Trailer(RequestHeaderState::Start));
+        }
+        ParseResult::Ok(ChunkedState::Data)
+    }
+
+    /// 読み込み済みのchunk-dataの範囲を返す
+    fn parse_data<T: AsRef<[u8]>>(&mut self, cursor: &mut Cursor<T>) -> ChunkedResult {
+        let available = remaining(cursor).len();
+        if available == 0 {
+            return ChunkedResult::Again;
+        }
+        let n = (available as u64).min(self.remaining) as usize;
+        let start = cursor.position() as usize;
+        advance(cursor, n);
+        self.remaining -= n as u64;
+        if self.remaining == 0 {
+            self.state = ChunkedState::DataCr;
+        }
+        ChunkedResult::Data(start..start + n)
+    }
+
+    fn parse_data_cr<T: AsRef<[u8]>>(
+        &mut self,
+        cursor: &mut Cursor<T>,
+        config: &ParserConfig,
+    ) -> ParseResult<ChunkedState> {
+        match read_byte(cursor) {
+            ReadResult::Ok(b'\r') => ParseResult::Ok(ChunkedState::DataLf),
+            ReadResult::Ok(b'\n') if config.allow_bare_lf => ParseResult::Ok(ChunkedState::Size),
+            ReadResult::Ok(_) => ParseResult::Error,
+            ReadResult::Again => ParseResult::Again(ChunkedState::DataCr),
+            ReadResult::Err => ParseResult::Error,
+        }
+    }
+
+    fn parse_data_lf<T: AsRef<[u8]>>(
+        &mut self,
+        cursor: &mut Cursor<T>,
+    ) -> ParseResult<ChunkedState> {
+        match read_byte(cursor) {
+            ReadResult::Ok(b'\n') => ParseResult::Ok(ChunkedState::Size),
+            ReadResult::Ok(_) => ParseResult::Error,
+            ReadResult::Again => ParseResult::Again(ChunkedState::DataLf),
+            ReadResult::Err => ParseResult::Error,
+        }
+    }
+
+    /// trailer sectionをヘッダーと同じように1行ずつ読み込む。
+    /// 空行を読み込んだらボディの終わりである。
+    fn parse_trailer<T: AsRef<[u8]>>(
+        &mut self,
+        cursor: &mut Cursor<T>,
+        state: RequestHeaderState,
+        config: &ParserConfig,
+    ) -> ParseResult<ChunkedState> {
+        if self.trailer.fields.is_empty() && matches!(state, RequestHeaderState::Start) {
+            self.trailer.header_start = cursor.position() as usize;
+        }
+        match parse_http_request_header(cursor, &mut self.trailer, &mut self.field, state, config) {
+            ParseResult::Complete if self.field.is_separator => ParseResult::Complete,
+            ParseResult::Complete => {
+                self.field = Field::new();
+                ParseResult::Ok(ChunkedState::Trailer(RequestHeaderState::Start))
+            }
+            ParseResult::Again(state) => ParseResult::Again(ChunkedState::Trailer(state)),
+            ParseResult::Ok(_) | ParseResult::Error => ParseResult::Error,
+            ParseResult::TooLarge => ParseResult::TooLarge,
+        }
+    }
+}
+
+impl Default for ChunkedDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// bufを全てデコードし, chunk-dataを連結したものを返す
+    fn decode_all(
+        buf: &[u8],
+        decoder: &mut ChunkedDecoder,
+        config: &ParserConfig,
+    ) -> Option<Vec<u8>> {
+        let mut cursor = Cursor::new(buf);
+        let mut body = Vec::new();
+        loop {
+            match decoder.decode(&mut cursor, config) {
+                ChunkedResult::Data(range) => body.extend_from_slice(&buf[range]),
+                ChunkedResult::Complete => return Some(body),
+                _ => return None,
+            }
+        }
+    }
+
+    #[test]
+    fn decode_chunked_body() {
+        let buf = b"4\r\nWiki\r\n5\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new();
+        let body = decode_all(buf, &mut decoder, &ParserConfig::default()).unwrap();
+        assert_eq!(body, b"Wikipedia in\r\n\r\nchunks.");
+        assert!(decoder.trailers().is_empty());
+        assert!(matches!(decoder.state, ChunkedState::End));
+    }
+
+    #[test]
+    fn decode_extensions_and_trailers() {
+        let buf = b"a;name=\"va;lue\" ; x\r\n0123456789\r\n000;last\r\nExpires: never\r\nX-Sum: 1\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new();
+        let body = decode_all(buf, &mut decoder, &ParserConfig::default()).unwrap();
+        assert_eq!(body, b"0123456789");
+        assert_eq!(decoder.trailers().len(), 2);
+        assert_eq!(decoder.trailers()[0].name_bytes(&buf), b"Expires");
+        assert_eq!(decoder.trailer("x-sum", &buf).unwrap().value(&buf), Ok("1"));
+    }
+
+    #[test]
+    fn resume_from_each_split_point() {
+        let buf = b"3;a=b\r\nabc\r\n10\r\n0123456789abcdef\r\n0\r\nX-Sum: 1\r\n\r\n";
+        let config = ParserConfig::default();
+        for split in 0..buf.len() {
+            let mut decoder = ChunkedDecoder::new();
+            let mut cursor = Cursor::new(&buf[..split]);
+            let mut body = Vec::new();
+            loop {
+                match decoder.decode(&mut cursor, &config) {
+                    ChunkedResult::Data(range) => body.extend_from_slice(&buf[range]),
+                    ChunkedResult::Again => break,
+                    _ => panic!("unexpected result at {}", split),
+                }
+            }
+            let pos = cursor.position();
+            let mut cursor = Cursor::new(&buf[..]);
+            cursor.set_position(pos);
+            loop {
+                match decoder.decode(&mut cursor, &config) {
+                    ChunkedResult::Data(range) => body.extend_from_slice(&buf[range]),
+                    ChunkedResult::Complete => break,
+                    _ => panic!("unexpected result at {}", split),
+                }
+            }
+            assert_eq!(body, b"abc0123456789abcdef");
+            assert_eq!(decoder.trailers().len(), 1);
+        }
+    }
+
+    #[test]
+    fn too_large_chunk_size() {
+        let config = ParserConfig {
+            max_chunk_size: 16,
+            ..ParserConfig::default()
+        };
+        let mut decoder = ChunkedDecoder::new();
+        let mut cursor = Cursor::new(b"11\r\n");
+        assert!(matches!(
+            decoder.decode(&mut cursor, &config),
+            ChunkedResult::TooLarge
+        ));
+
+        let mut decoder = ChunkedDecoder::new();
+        let mut cursor = Cursor::new(b"10\r\n");
+        assert!(matches!(
+            decoder.decode(&mut cursor, &config),
+            ChunkedResult::Again
+        ));
+
+        // 先頭の0が多くても値が上限以下であれば受け付ける
+        let mut decoder = ChunkedDecoder::new();
+        let mut cursor = Cursor::new(b"0000000000000000000010\r\n");
+        assert!(matches!(
+            decoder.decode(&mut cursor, &config),
+            ChunkedResult::Again
+        ));
+
+        let mut decoder = ChunkedDecoder::new();
+        let mut cursor = Cursor::new(b"fffffffffffffffffffff\r\n");
+        let config = ParserConfig::default();
+        assert!(matches!(
+            decoder.decode(&mut cursor, &config),
+            ChunkedResult::TooLarge
+        ));
+    }
+
+    #[test]
+    fn invalid_chunked_body_should_failed() {
+        let config = ParserConfig::default();
+        for buf in [
+            &b"\r\n"[..],
+            b"x\r\n",
+            b"-1\r\n",
+            b"3\r\nabcd\r\n0\r\n\r\n",
+            b"3\rabc\r\n0\r\n\r\n",
+            b"3;\x01\r\nabc\r\n0\r\n\r\n",
+            b"0\r\nX-Sum 1\r\n\r\n",
+        ] {
+            let mut decoder = ChunkedDecoder::new();
+            assert!(
+                decode_all(buf, &mut decoder, &config).is_none(),
+                "{}",
+                String::from_utf8_lossy(buf)
+            );
+        }
+    }
+
+    #[test]
+    fn bare_lf_follows_config() {
+        let buf = b"3\nabc\n0\n\n";
+        let mut decoder = ChunkedDecoder::new();
+        let body = decode_all(buf, &mut decoder, &ParserConfig::lenient()).unwrap();
+        assert_eq!(body, b"abc");
+
+        let mut decoder = ChunkedDecoder::new();
+        assert!(decode_all(buf, &mut decoder, &ParserConfig::strict()).is_none());
+    }
+}
@@ -1,7 +1,21 @@
-use std::io::{Cursor, Read};
+use std::io::Cursor;
 
-use super::http_interface::{Field, ParseResult, HTTPHeader};
-use super::parse_utility::{is_tchar, is_vchar, read_byte, ReadResult};
+use memchr::{memchr, memchr2};
+
+use crate::error::RashinErr;
+
+use super::config::{ObsFold, ParserConfig};
+use super::header_name::KnownHeader;
+use super::header_value::{
+    is_valid_upgrade, parse_connection_options, parse_digits, parse_expect, parse_te,
+    parse_transfer_encoding, MediaType, TransferCoding,
+};
+use super::http_interface::{Field, HTTPHeader, HttpVersion, ParseResult};
+use super::parse_utility::{
+    advance, all_field_content, all_tchar, is_field_vchar, is_tchar, read_byte, remaining,
+    ReadResult,
+};
+use super::uri::Host;
 
 #[derive(Clone, Debug)]
 pub enum RequestHeaderState {
@@ -9,112 +23,205 @@ pub enum RequestHeaderState {
     FieldName,
     OWS1,
     FieldValue,
-    OWS2,
+    /// obs-foldで続く直前のヘッダーの値
+    ObsFold,
     End,
 }
 
-pub fn parse_http_request_header<'a, T: AsRef<[u8]>>(
-    cursor: &'a mut Cursor<T>,
+/// ヘッダーを1行パースする。
+/// パースが完了したヘッダーはheaderに追加する。空行の場合はfield.is_separatorがtrueになる。
+///
+/// 入力が途中で途切れた場合はAgain(state)を返す。fieldにはそれまでに読み込んだ位置が
+/// 記録されているので、追加のデータを読み込んだ後に同じfieldとstateを渡せば続きからパースできる。
+///
+/// 以下の場合はTooLargeを返す。
+/// * 1行の長さがconfig.max_header_field_sizeを超えた
+/// * header.header_startからのヘッダーセクションの長さがconfig.max_header_section_sizeを超えた
+/// * ヘッダーの数がconfig.max_header_fieldsを超えた
+pub fn parse_http_request_header<T: AsRef<[u8]>>(
+    cursor: &mut Cursor<T>,
+    header: &mut HTTPHeader,
     field: &mut Field,
+    mut state: RequestHeaderState,
+    config: &ParserConfig,
 ) -> ParseResult<RequestHeaderState> {
-    let mut state = RequestHeaderState::Start;
+    // 途中から再開する場合は前回読み込んだ行頭の位置を使う
+    let line_start = match state {
+        RequestHeaderState::Start => cursor.position() as usize,
+        _ => field.name_start,
+    };
 
     loop {
         let result = match state {
-            RequestHeaderState::Start => parse_start(cursor, field),
-            RequestHeaderState::FieldName => parse_name(cursor, field),
-            RequestHeaderState::OWS1 => parse_ows_before_value(cursor, field),
-            RequestHeaderState::FieldValue => parse_field_value(cursor, field),
-            RequestHeaderState::OWS2 => parse_ows_after_value(cursor, field),
-            RequestHeaderState::End => parse_ows_after_value(cursor, field),
+            RequestHeaderState::Start => parse_start(cursor, header, field, config),
+            RequestHeaderState::FieldName => parse_name(cursor, field, config),
+            RequestHeaderState::OWS1 => parse_ows_before_value(cursor, field, config),
+            RequestHeaderState::FieldValue => parse_field_value(cursor, field, config),
+            RequestHeaderState::ObsFold => match header.fields.last_mut() {
+                Some(folded) => match parse_field_value(cursor, folded, config) {
+                    ParseResult::Again(_) => ParseResult::Again(RequestHeaderState::ObsFold),
+                    result => result,
+                },
+                None => ParseResult::Error,
+            },
+            RequestHeaderState::End => parse_end_lf(cursor, field),
         };
 
+        let position = cursor.position() as usize;
+        if position - line_start > config.max_header_field_size
+            || position.saturating_sub(header.header_start) > config.max_header_section_size
+        {
+            return ParseResult::TooLarge;
+        }
+
         match result {
             ParseResult::Ok(next_state) => {
                 state = next_state.clone();
             }
             ParseResult::Again(state) => return ParseResult::Again(state),
-            ParseResult::Complete => return ParseResult::Complete,
+            ParseResult::Complete => {
+                if field.is_separator {
+                    return ParseResult::Complete;
+                }
+                if field.is_obs_fold {
+                    if let Some(folded) = header.fields.last_mut() {
+                        folded.has_obs_fold = true;
+                    }
+                    return ParseResult::Complete;
+                }
+                if header.field_size >= config.max_header_fields {
+                    return ParseResult::TooLarge;
+                }
+                header.add_field(*field);
+                return ParseResult::Complete;
+            }
             ParseResult::Error => return ParseResult::Error,
+            ParseResult::TooLarge => return ParseResult::TooLarge,
         }
     }
 }
 
 /// ヘッダー行の冒頭を読み込む。
 /// * 読み込んだ文字が有効なfield-nameの文字であればfield-nameのパースに遷移する。
+/// * SPまたはHTABの場合はobs-foldなので, config.obs_foldに従って拒否するか直前の値の続きとして読む。
+/// * LFだけの空行はconfig.allow_bare_lfが有効な場合のみヘッダーセクションの終わりとして扱う。
 ///
 fn parse_start<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
+    header: &HTTPHeader,
     field: &mut Field,
+    config: &ParserConfig,
 ) -> ParseResult<RequestHeaderState> {
     let read_result = read_byte(cursor);
+    if let ReadResult::Ok(_) = read_result {
+        field.name_start = cursor.position() as usize - 1;
+    }
     match read_result {
         ReadResult::Ok(b'\r') => {
             field.is_separator = true;
-            return ParseResult::Ok(RequestHeaderState::End);
+            ParseResult::Ok(RequestHeaderState::End)
         }
         ReadResult::Ok(b'\n') => {
+            if !config.allow_bare_lf {
+                return ParseResult::Error;
+            }
             field.is_separator = true;
-            return ParseResult::Complete;
+            ParseResult::Complete
+        }
+        ReadResult::Ok(b' ') | ReadResult::Ok(b'\t') => {
+            // 最初のヘッダーの前の空白は続けるべき値が無いのでエラーとする
+            if config.obs_fold == ObsFold::Reject || header.fields.is_empty() {
+                return ParseResult::Error;
+            }
+            field.is_obs_fold = true;
+            ParseResult::Ok(RequestHeaderState::ObsFold)
         }
         ReadResult::Ok(c) => {
             if !is_tchar(c) {
                 return ParseResult::Error;
             }
-            field.name_start = cursor.position() as usize - 1;
-            return ParseResult::Ok(RequestHeaderState::FieldName);
-        }
-        ReadResult::Again => {
-            return ParseResult::Again(RequestHeaderState::Start);
+            ParseResult::Ok(RequestHeaderState::FieldName)
         }
-        ReadResult::Err => {
-            return ParseResult::Error;
-        }
-    };
+        ReadResult::Again => ParseResult::Again(RequestHeaderState::Start),
+        ReadResult::Err => ParseResult::Error,
+    }
 }
 
-// field-nameをパースする。
+/// field-nameをパースする。
+/// ":"をmemchrでまとめて探し、その手前がtokenであることを一度に検証する。
+///
+/// field-nameと":"の間の空白はRFC9112で拒否しなければならないとされているが,
+/// config.allow_space_before_colonが有効な場合は空白を取り除いてfield-nameとする。
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9112#section-5.1
 fn parse_name<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
     field: &mut Field,
+    config: &ParserConfig,
 ) -> ParseResult<RequestHeaderState> {
-    loop {
-        let read_result = read_byte(cursor);
-        match read_result {
-            ReadResult::Ok(b':') => {
-                field.name_end = cursor.position() as usize - 1;
-                return ParseResult::Ok(RequestHeaderState::OWS1);
-            }
-            ReadResult::Ok(c) => {
-                if !is_tchar(c) {
-                    return ParseResult::Error;
-                }
-            }
-            ReadResult::Again => {
-                return ParseResult::Again(RequestHeaderState::FieldName);
-            }
-            ReadResult::Err => {
-                return ParseResult::Error;
-            }
-        };
+    let rest = remaining(cursor);
+    let (candidate, found) = match memchr(b':', rest) {
+        Some(n) => (&rest[..n], true),
+        None => (rest, false),
+    };
+    let name_len = if config.allow_space_before_colon {
+        candidate
+            .iter()
+            .rposition(|&c| c != b' ' && c != b'\t')
+            .map_or(0, |n| n + 1)
+    } else {
+        candidate.len()
+    };
+    if !all_tchar(&candidate[..name_len]) {
+        return ParseResult::Error;
+    }
+
+    if found {
+        field.name_end = cursor.position() as usize + name_len;
+        field.known = KnownHeader::from_name(field.name_bytes(cursor.get_ref()));
+        advance(cursor, candidate.len() + 1);
+        ParseResult::Ok(RequestHeaderState::OWS1)
+    } else {
+        // 末尾の空白の後にfield-nameが続くかもしれないので, 空白は次回読み直す
+        advance(cursor, name_len);
+        ParseResult::Again(RequestHeaderState::FieldName)
     }
 }
 
+/// field-valueの前のOWSを読み飛ばす。値が空の場合はそのまま行末に遷移する。
+///
+/// OWS = *( SP / HTAB )
 fn parse_ows_before_value<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
     field: &mut Field,
+    config: &ParserConfig,
 ) -> ParseResult<RequestHeaderState> {
     loop {
         let read_result = read_byte(cursor);
         match read_result {
-            ReadResult::Ok(b' ') => {
+            ReadResult::Ok(b' ') | ReadResult::Ok(b'\t') => {
                 continue;
             }
+            ReadResult::Ok(b'\r') => {
+                field.value_start = cursor.position() as usize - 1;
+                field.value_end = field.value_start;
+                return ParseResult::Ok(RequestHeaderState::End);
+            }
+            ReadResult::Ok(b'\n') => {
+                if !config.allow_bare_lf {
+                    return ParseResult::Error;
+                }
+                field.value_start = cursor.position() as usize - 1;
+                field.value_end = field.value_start;
+                return ParseResult::Complete;
+            }
             ReadResult::Ok(c) => {
-                if !is_vchar(c) {
+                if !is_field_vchar(c) {
                     return ParseResult::Error;
                 }
                 field.value_start = cursor.position() as usize - 1;
+                field.value_end = cursor.position() as usize;
                 return ParseResult::Ok(RequestHeaderState::FieldValue);
             }
             ReadResult::Again => {
@@ -133,200 +240,940 @@ fn parse_ows_before_value<T: AsRef<[u8]>>(
 /// field-content = field-vchar [ 1*( SP / HTAB / field-vchar ) field-vchar ]
 /// field-vchar = VCHAR / obs-text
 /// ただしvcharはSection2.1に記載のある表示可能な文字である。
-/// 
+///
+/// 末尾の空白はfield-valueに含まれないので、最後に読み込んだfield-vcharの直後を
+/// field.value_endとして記録しておく。こうしておけば途中で中断しても続きから再開できる。
+/// 行末のCR/LFはmemchr2でまとめて探し、その手前を一度に検証する。
+///
 /// References:
 /// https://www.rfc-editor.org/rfc/rfc9110#name-syntax-notation
 fn parse_field_value<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
     field: &mut Field,
+    config: &ParserConfig,
 ) -> ParseResult<RequestHeaderState> {
-    let mut prev_ws = false;
-    loop {
-        let read_result = read_byte(cursor);
-        match read_result {
-            ReadResult::Ok(b'\r') => {
-                field.value_end = cursor.position() as usize - 1;
-                return ParseResult::Ok(RequestHeaderState::End);
-            }
-            ReadResult::Ok(b'\n') => {
-                field.value_end = cursor.position() as usize - 1;
-                return ParseResult::Complete;
-            }
-            ReadResult::Ok(b' ') | ReadResult::Ok(b'\t') => {
-                if prev_ws {
-                    return ParseResult::Ok(RequestHeaderState::OWS2);
-                } else {
-                    field.value_end = cursor.position() as usize - 1;
-                    prev_ws = true;
-                }
-            }
-            ReadResult::Ok(c) => {
-                if is_vchar(c) {
-                    prev_ws = false;
-                } else {
-                    return ParseResult::Error;
-                }
-            }
-            ReadResult::Again => {
-                return ParseResult::Again(RequestHeaderState::FieldValue);
-            }
-            ReadResult::Err => {
-                return ParseResult::Error;
-            }
-        };
+    let rest = remaining(cursor);
+    let (content, line_end) = match memchr2(b'\r', b'\n', rest) {
+        Some(n) => (&rest[..n], Some(rest[n])),
+        None => (rest, None),
+    };
+    if !all_field_content(content) {
+        return ParseResult::Error;
+    }
+    let start = cursor.position() as usize;
+    if let Some(n) = content.iter().rposition(|&c| c != b' ' && c != b'\t') {
+        field.value_end = start + n + 1;
     }
-}
 
-fn parse_ows_after_value<T: AsRef<[u8]>>(
-    cursor: &mut Cursor<T>,
-    field: &mut Field,
-) -> ParseResult<RequestHeaderState> {
-    loop {
-        let read_result = read_byte(cursor);
-        match read_result {
-            ReadResult::Ok(b' ') => {
-                continue;
-            }
-            ReadResult::Ok(c) => {
-                if c == b' ' {
-                    continue;
-                }
-                if c == b'\r' {
-                    return ParseResult::Ok(RequestHeaderState::End);
-                }
-                if c == b'\n' {
-                    return ParseResult::Complete;
-                }
-                return ParseResult::Error;
-            }
-            ReadResult::Again => {
-                return ParseResult::Again(RequestHeaderState::OWS2);
-            }
-            ReadResult::Err => {
+    match line_end {
+        Some(b'\r') => {
+            advance(cursor, content.len() + 1);
+            ParseResult::Ok(RequestHeaderState::End)
+        }
+        Some(_) => {
+            if !config.allow_bare_lf {
                 return ParseResult::Error;
             }
-        };
+            advance(cursor, content.len() + 1);
+            ParseResult::Complete
+        }
+        None => {
+            advance(cursor, content.len());
+            ParseResult::Again(RequestHeaderState::FieldValue)
+        }
     }
 }
 
+/// CRの直後にLFが続くことを確認する
 fn parse_end_lf<T: AsRef<[u8]>>(
-    cursor: &mut Cursor<&T>,
-    field: &mut Field,
+    cursor: &mut Cursor<T>,
+    _field: &mut Field,
 ) -> ParseResult<RequestHeaderState> {
     let read_result = read_byte(cursor);
     match read_result {
-        ReadResult::Ok(b'\n') => {
-            return ParseResult::Complete;
+        ReadResult::Ok(b'\n') => ParseResult::Complete,
+        ReadResult::Ok(_) => ParseResult::Error,
+        ReadResult::Again => ParseResult::Again(RequestHeaderState::End),
+        ReadResult::Err => ParseResult::Error,
+    }
+}
+
+/// obs-foldを含むヘッダーの値の改行をバッファ上でSPに置き換える。
+/// 値はバッファの一部として返すので, 置き換えておかないとCR/LFがハンドラーに渡り,
+/// 値をそのまま送り返したり転送したりした場合にヘッダーを注入されてしまう。
+/// 全てのヘッダーを読み終えた後, 値を参照する前に呼び出す。
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9112#name-obsolete-line-folding
+pub fn replace_obs_fold(http_header: &HTTPHeader, buffer: &mut [u8]) {
+    for field in http_header.fields.iter().filter(|field| field.has_obs_fold) {
+        for c in &mut buffer[field.value_start..field.value_end] {
+            if *c == b'\r' || *c == b'\n' {
+                *c = b' ';
+            }
         }
-        ReadResult::Ok(_) => {
-            return ParseResult::Error;
+    }
+}
+
+/// サーバーが解釈するヘッダーの値を読み込み, HTTPHeaderに保存する。
+/// 値の構文が正しくない場合はエラーを返すので, ハンドラーは400で応答する。
+pub fn process_reserved_header<T: AsRef<[u8]>>(
+    http_header: &mut HTTPHeader,
+    field: &Field,
+    buffer: &T,
+) -> Result<(), RashinErr> {
+    log::debug!(
+        "Field: {} = {}",
+        String::from_utf8_lossy(field.name_bytes(buffer)),
+        field.value_lossy(buffer)
+    );
+    let known = match field.known {
+        Some(known) => known,
+        None => return Ok(()),
+    };
+    let value = field.value_bytes(buffer);
+    let valid = match known {
+        KnownHeader::Host => {
+            // Hostが複数あるリクエストは400で拒否しなければならない
+            if http_header.host.is_some() {
+                return Err(RashinErr::InvalidHeader("duplicate Host"));
+            }
+            match Host::parse(value, field.value_start) {
+                Some(host) => {
+                    http_header.host = Some(*field);
+                    // absolute-formの場合はrequest-targetのホストを優先する
+                    http_header.target_host.get_or_insert(host);
+                    true
+                }
+                None => false,
+            }
         }
-        ReadResult::Again => {
-            return ParseResult::Again(RequestHeaderState::End);
+        KnownHeader::ContentLength => match parse_digits(value) {
+            // 同じ値の繰り返しであっても, 前後のプロキシと解釈が食い違わないように拒否する
+            Some(_) if http_header.content_length.is_some() => {
+                return Err(RashinErr::AmbiguousFraming("duplicate Content-Length"));
+            }
+            Some(length) => {
+                http_header.content_length = Some(length);
+                true
+            }
+            None => false,
+        },
+        KnownHeader::TransferEncoding => {
+            parse_transfer_encoding(value, &mut http_header.transfer_encoding)
         }
-        ReadResult::Err => {
-            return ParseResult::Error;
+        KnownHeader::Connection => process_connection(http_header, value),
+        KnownHeader::Expect => match parse_expect(value) {
+            Some(expectation) => {
+                http_header.expect = Some(expectation);
+                true
+            }
+            None => false,
+        },
+        KnownHeader::Upgrade => {
+            http_header.upgrade = Some(*field);
+            is_valid_upgrade(value)
         }
+        KnownHeader::Te => match parse_te(value) {
+            Some(trailers) => {
+                http_header.te_trailers |= trailers;
+                true
+            }
+            None => false,
+        },
+        KnownHeader::ContentType => match MediaType::parse(value, field.value_start) {
+            Some(media_type) => {
+                http_header.content_type = Some(media_type);
+                true
+            }
+            None => false,
+        },
+        _ => true,
     };
+    if !valid {
+        return Err(RashinErr::InvalidHeader(known.as_str()));
+    }
+    Ok(())
 }
 
+/// 全てのヘッダーを処理した後に, ボディの長さの決め方が曖昧でないことを確認する。
+/// 前後のプロキシとボディの境界の解釈が食い違うとリクエストスマグリングに利用されるので,
+/// 曖昧なリクエストは400で拒否して接続を閉じる。
+///
+/// * Content-LengthとTransfer-Encodingが両方ある
+/// * Transfer-Encodingの最後がchunkedでない, またはchunkedが複数回適用されている
+/// * HTTP/1.0のリクエストにTransfer-Encodingがある
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9112#name-message-body-length
+pub fn validate_framing(http_header: &HTTPHeader) -> Result<(), RashinErr> {
+    if http_header.transfer_encoding.is_empty() {
+        return Ok(());
+    }
+    if http_header.content_length.is_some() {
+        return Err(RashinErr::AmbiguousFraming(
+            "both Content-Length and Transfer-Encoding",
+        ));
+    }
+    if http_header.version < HttpVersion::HTTP_1_1 {
+        return Err(RashinErr::AmbiguousFraming("Transfer-Encoding in HTTP/1.0"));
+    }
+    let chunked = http_header
+        .transfer_encoding
+        .iter()
+        .filter(|coding| **coding == TransferCoding::Chunked)
+        .count();
+    if !http_header.is_chunked() || chunked > 1 {
+        return Err(RashinErr::AmbiguousFraming(
+            "chunked is not the final coding",
+        ));
+    }
+    Ok(())
+}
 
-pub fn process_reserved_header(http_header: &mut HTTPHeader, field_name: &str, field_value: &str) {
-    match field_name {
-        _ => {
-            log::debug!(
-                "Field: {} = {}", field_name, field_value
-            );
+/// Connectionヘッダーのconnection-optionから接続を維持するかどうかを決める。
+/// closeとkeep-aliveが両方指定された場合はcloseを優先する。
+/// connection-optionがtokenでない場合はfalseを返す。
+///
+/// Connection = #connection-option
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9112#name-persistence
+fn process_connection(http_header: &mut HTTPHeader, field_value: &[u8]) -> bool {
+    let options = match parse_connection_options(field_value) {
+        Some(options) => options,
+        None => return false,
+    };
+    for option in options {
+        if option.eq_ignore_ascii_case(b"close") {
+            http_header.keep_alive = Some(false);
+        } else if option.eq_ignore_ascii_case(b"keep-alive") && http_header.keep_alive.is_none() {
+            http_header.keep_alive = Some(true);
         }
     }
+    true
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::http::header_value::Expectation;
     use bytes::Bytes;
 
     #[test]
     fn parse_host_header_successfully() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         let mut field = Field::new();
         let buf = Bytes::from("Host: localhost:8080\r\n");
         let mut cursor = Cursor::new(&buf);
-        let result = parse_http_request_header(&mut cursor, &mut field);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
         assert!(matches!(result, ParseResult::Complete));
-        assert_eq!(field.name(&buf), "Host");
-        assert_eq!(field.value(&buf), "localhost:8080");
+        assert_eq!(field.name_bytes(&buf), b"Host");
+        assert_eq!(field.value(&buf), Ok("localhost:8080"));
     }
 
     #[test]
     fn parse_header_with_ows_successfully() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         let mut field = Field::new();
         let buf = Bytes::from("Host:     localhost:8080      \r\n");
         let mut cursor = Cursor::new(&buf);
-        let result = parse_http_request_header(&mut cursor, &mut field);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
 
         assert!(matches!(result, ParseResult::Complete));
-        assert_eq!(field.name(&buf), "Host");
-        assert_eq!(field.value(&buf), "localhost:8080");
+        assert_eq!(field.name_bytes(&buf), b"Host");
+        assert_eq!(field.value(&buf), Ok("localhost:8080"));
     }
 
     #[test]
     fn parse_header_end_with_lf_successfully() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         let mut field = Field::new();
         let buf = Bytes::from("Host: localhost:8080\n");
-        let mut cursor = &mut Cursor::new(&buf);
-        let result = parse_http_request_header(&mut cursor, &mut field);
+        let mut cursor = Cursor::new(&buf);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
         assert!(matches!(result, ParseResult::Complete));
-        assert_eq!(field.name(&buf), "Host");
-        assert_eq!(field.value(&buf), "localhost:8080");
+        assert_eq!(field.name_bytes(&buf), b"Host");
+        assert_eq!(field.value(&buf), Ok("localhost:8080"));
     }
 
     #[test]
     fn parse_consecutive_headers_successfully() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         let mut field = Field::new();
         let buf = Bytes::from("Host: localhost:8080\r\nContentType: text-html\r\n");
         let mut cursor = Cursor::new(&buf);
 
-        let result1 = parse_http_request_header(&mut cursor, &mut field);
+        let result1 = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
         assert!(matches!(result1, ParseResult::Complete));
-        assert_eq!(field.name(&buf), "Host");
-        assert_eq!(field.value(&buf), "localhost:8080");
+        assert_eq!(field.name_bytes(&buf), b"Host");
+        assert_eq!(field.value(&buf), Ok("localhost:8080"));
 
-        let result2 = parse_http_request_header(&mut cursor, &mut field);
+        let result2 = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
         assert!(matches!(result2, ParseResult::Complete));
-        assert_eq!(field.name(&buf), "ContentType");
-        assert_eq!(field.value(&buf), "text-html");
+        assert_eq!(field.name_bytes(&buf), b"ContentType");
+        assert_eq!(field.value(&buf), Ok("text-html"));
     }
 
     #[test]
     fn parse_paused_input_successfully() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         let mut field = Field::new();
         let mut buf = "Host: local".as_bytes().to_vec();
         let mut cursor = Cursor::new(&mut buf);
 
-        let result1 = parse_http_request_header(&mut cursor, &mut field);
-        assert!(matches!(result1, ParseResult::Again(RequestHeaderState::FieldValue)));
-        assert_eq!(field.name(cursor.get_ref()), "Host");
+        let result1 = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(
+            result1,
+            ParseResult::Again(RequestHeaderState::FieldValue)
+        ));
+        assert_eq!(field.name(cursor.get_ref()), Ok("Host"));
+    }
+
+    #[test]
+    fn cr_without_lf_should_failed() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let mut field = Field::new();
+        let buf = Bytes::from("Host: localhost:8080\r \n");
+        let mut cursor = Cursor::new(&buf);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Error));
+    }
+
+    #[test]
+    fn connection_header_overrides_default() {
+        let mut header = HTTPHeader::new();
+        header.version = HttpVersion::HTTP_1_0;
+        assert!(!header.is_keep_alive());
+        let buf = Bytes::from("Connection: Keep-Alive\r\n");
+        let config = ParserConfig::default();
+        let mut field = Field::new();
+        parse_http_request_header(
+            &mut Cursor::new(&buf),
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        process_reserved_header(&mut header, &field, &buf).unwrap();
+        assert!(header.is_keep_alive());
+
+        let mut header = HTTPHeader::new();
+        let buf = Bytes::from("connection: keep-alive, close\r\n");
+        let config = ParserConfig::default();
+        let mut field = Field::new();
+        parse_http_request_header(
+            &mut Cursor::new(&buf),
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        process_reserved_header(&mut header, &field, &buf).unwrap();
+        assert!(!header.is_keep_alive());
+    }
+
+    #[test]
+    fn parsed_fields_are_added_to_header() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let buf = Bytes::from("Host: localhost\r\nAccept: */*\r\n\r\n");
+        let mut cursor = Cursor::new(&buf);
+        loop {
+            let mut field = Field::new();
+            let result = parse_http_request_header(
+                &mut cursor,
+                &mut header,
+                &mut field,
+                RequestHeaderState::Start,
+                &config,
+            );
+            assert!(matches!(result, ParseResult::Complete));
+            if field.is_separator {
+                break;
+            }
+        }
+        assert_eq!(header.field_size, 2);
+        assert_eq!(header.fields[1].name_bytes(&buf), b"Accept");
+    }
+
+    #[test]
+    fn too_large_header_field() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig {
+            max_header_field_size: 16,
+            ..ParserConfig::default()
+        };
+        let mut field = Field::new();
+        let buf = Bytes::from("Host: localhost\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::TooLarge));
+    }
+
+    #[test]
+    fn too_large_header_section() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig {
+            max_header_section_size: 32,
+            ..ParserConfig::default()
+        };
+        let buf = Bytes::from("Host: localhost\r\nAccept: */*\r\nUser-Agent: test\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut field = Field::new();
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        let mut field = Field::new();
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        let mut field = Field::new();
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::TooLarge));
+    }
+
+    #[test]
+    fn too_many_header_fields() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig {
+            max_header_fields: 2,
+            ..ParserConfig::default()
+        };
+        let buf = Bytes::from("A: 1\r\nB: 2\r\nC: 3\r\n");
+        let mut cursor = Cursor::new(&buf);
+        for _ in 0..2 {
+            let mut field = Field::new();
+            let result = parse_http_request_header(
+                &mut cursor,
+                &mut header,
+                &mut field,
+                RequestHeaderState::Start,
+                &config,
+            );
+            assert!(matches!(result, ParseResult::Complete));
+        }
+        let mut field = Field::new();
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::TooLarge));
+    }
+
+    #[test]
+    fn parse_value_with_inner_spaces() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let mut field = Field::new();
+        let buf = Bytes::from("User-Agent: Mozilla/5.0  (X11; Linux)  \r\n");
+        let mut cursor = Cursor::new(&buf);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(field.value(&buf), Ok("Mozilla/5.0  (X11; Linux)"));
+    }
+
+    #[test]
+    fn resume_from_each_split_point() {
+        let full = "Host:  localhost:8080 \r\n";
+        let config = ParserConfig::default();
+        for split in 0..full.len() {
+            let mut header = HTTPHeader::new();
+            let mut field = Field::new();
+
+            let buf = Bytes::from(&full[..split]);
+            let mut cursor = Cursor::new(&buf);
+            let result = parse_http_request_header(
+                &mut cursor,
+                &mut header,
+                &mut field,
+                RequestHeaderState::Start,
+                &config,
+            );
+            let state = match result {
+                ParseResult::Again(state) => state,
+                _ => panic!("split at {} should be incomplete", split),
+            };
+            let pos = cursor.position();
+
+            let buf = Bytes::from(full);
+            let mut cursor = Cursor::new(&buf);
+            cursor.set_position(pos);
+            let result =
+                parse_http_request_header(&mut cursor, &mut header, &mut field, state, &config);
+            assert!(matches!(result, ParseResult::Complete));
+            assert_eq!(field.name_bytes(&buf), b"Host");
+            assert_eq!(field.value(&buf), Ok("localhost:8080"));
+            assert_eq!(header.field_size, 1);
+        }
+    }
+
+    #[test]
+    fn parse_header_with_htab_ows() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let mut field = Field::new();
+        let buf = Bytes::from("Host:\t \tlocalhost:8080\t \r\n");
+        let mut cursor = Cursor::new(&buf);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(field.value(&buf), Ok("localhost:8080"));
+    }
+
+    #[test]
+    fn parse_header_with_empty_value() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let mut field = Field::new();
+        let buf = Bytes::from("X-Empty:  \r\n");
+        let mut cursor = Cursor::new(&buf);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(field.name_bytes(&buf), b"X-Empty");
+        assert_eq!(field.value(&buf), Ok(""));
+    }
+
+    #[test]
+    fn parse_header_with_obs_text() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let mut field = Field::new();
+        let buf = b"X-Name: caf\xe9 \xff\r\n".to_vec();
+        let mut cursor = Cursor::new(&buf);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(field.value_bytes(&buf), b"caf\xe9 \xff");
+        assert!(field.value(&buf).is_err());
+        assert_eq!(field.value_lossy(&buf), "caf\u{fffd} \u{fffd}");
+    }
+
+    #[test]
+    fn control_character_in_value_should_failed() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let mut field = Field::new();
+        let buf = Bytes::from("X-Name: a\x01b\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
+        assert!(matches!(result, ParseResult::Error));
+    }
+
+    fn parse_all_fields(
+        buf: &[u8],
+        header: &mut HTTPHeader,
+        config: &ParserConfig,
+    ) -> ParseResult<RequestHeaderState> {
+        let mut cursor = Cursor::new(buf);
+        loop {
+            let mut field = Field::new();
+            let result = parse_http_request_header(
+                &mut cursor,
+                header,
+                &mut field,
+                RequestHeaderState::Start,
+                config,
+            );
+            if !matches!(result, ParseResult::Complete) || field.is_separator {
+                return result;
+            }
+        }
+    }
+
+    #[test]
+    fn obs_fold_is_rejected_by_default() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let buf = b"X-Long: first\r\n  second\r\n\r\n";
+        let result = parse_all_fields(buf, &mut header, &config);
+        assert!(matches!(result, ParseResult::Error));
+    }
+
+    #[test]
+    fn obs_fold_is_replaced_with_sp() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig {
+            obs_fold: ObsFold::Replace,
+            ..ParserConfig::default()
+        };
+        let mut buf = *b"X-Long: first\r\n  second\r\n\tthird \r\nHost: localhost\r\n\r\n";
+        let result = parse_all_fields(&buf, &mut header, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.field_size, 2);
+        replace_obs_fold(&header, &mut buf);
+        assert_eq!(
+            header.fields[0].value_bytes(&buf),
+            b"first    second  \tthird"
+        );
+        assert_eq!(header.fields[1].value(&buf), Ok("localhost"));
+    }
+
+    #[test]
+    fn whitespace_before_first_header_should_failed() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig {
+            obs_fold: ObsFold::Replace,
+            ..ParserConfig::default()
+        };
+        let buf = b" Host: localhost\r\n\r\n";
+        let result = parse_all_fields(buf, &mut header, &config);
+        assert!(matches!(result, ParseResult::Error));
     }
 
     #[test]
     fn parse_empty_line_crlf() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         let mut field = Field::new();
         let buf = Bytes::from("\r\n");
         let mut cursor = Cursor::new(&buf);
-        let result = parse_http_request_header(&mut cursor, &mut field);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
         assert!(field.is_separator);
         assert!(matches!(result, ParseResult::Complete));
     }
 
     #[test]
     fn parse_empty_line_lf() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         let mut field = Field::new();
         let buf = Bytes::from("\n");
         let mut cursor = Cursor::new(&buf);
-        let result = parse_http_request_header(&mut cursor, &mut field);
+        let result = parse_http_request_header(
+            &mut cursor,
+            &mut header,
+            &mut field,
+            RequestHeaderState::Start,
+            &config,
+        );
         assert!(field.is_separator);
         assert!(matches!(result, ParseResult::Complete));
     }
-}
 
+    #[test]
+    fn space_before_colon_should_failed_by_default() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let buf = b"Host : localhost\r\n\r\n";
+        let result = parse_all_fields(buf, &mut header, &config);
+        assert!(matches!(result, ParseResult::Error));
+    }
+
+    #[test]
+    fn lenient_profile_trims_space_before_colon() {
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::lenient();
+        let mut buf = *b"Host \t: localhost\nX-Long: first\n second\n\n";
+        let result = parse_all_fields(&buf, &mut header, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.field_size, 2);
+        replace_obs_fold(&header, &mut buf);
+        assert_eq!(header.fields[0].name_bytes(&buf), b"Host");
+        assert_eq!(header.fields[0].value(&buf), Ok("localhost"));
+        assert_eq!(header.fields[1].value(&buf), Ok("first  second"));
+
+        // 空白の後に名前が続く場合は空白を取り除いても不正
+        let mut header = HTTPHeader::new();
+        let buf = b"Ho st: localhost\r\n\r\n";
+        let result = parse_all_fields(buf, &mut header, &config);
+        assert!(matches!(result, ParseResult::Error));
+    }
+
+    #[test]
+    fn lenient_profile_resumes_inside_space_before_colon() {
+        let config = ParserConfig::lenient();
+        let buf = b"Host  : localhost\r\n";
+        for split in 1..buf.len() {
+            let mut header = HTTPHeader::new();
+            let mut field = Field::new();
+            let mut cursor = Cursor::new(&buf[..split]);
+            let state = match parse_http_request_header(
+                &mut cursor,
+                &mut header,
+                &mut field,
+                RequestHeaderState::Start,
+                &config,
+            ) {
+                ParseResult::Again(state) => state,
+                _ => panic!("unexpected result at {}", split),
+            };
+            let pos = cursor.position();
+            let mut cursor = Cursor::new(&buf[..]);
+            cursor.set_position(pos);
+            let result =
+                parse_http_request_header(&mut cursor, &mut header, &mut field, state, &config);
+            assert!(matches!(result, ParseResult::Complete));
+            assert_eq!(field.name_bytes(&buf), b"Host");
+            assert_eq!(field.value(&buf), Ok("localhost"));
+        }
+    }
+
+    #[test]
+    fn strict_profile_rejects_bare_lf() {
+        let config = ParserConfig::strict();
+        for buf in [
+            &b"Host: localhost\n\r\n"[..],
+            &b"Host:\n\r\n"[..],
+            &b"Host: localhost\r\n\n"[..],
+        ] {
+            let mut header = HTTPHeader::new();
+            let result = parse_all_fields(buf, &mut header, &config);
+            assert!(matches!(result, ParseResult::Error));
+        }
+
+        let mut header = HTTPHeader::new();
+        let buf = b"Host: localhost\r\n\r\n";
+        let result = parse_all_fields(buf, &mut header, &config);
+        assert!(matches!(result, ParseResult::Complete));
+    }
+
+    fn process_all_fields(buf: &[u8], header: &mut HTTPHeader) -> Result<(), RashinErr> {
+        let config = ParserConfig::default();
+        let result = parse_all_fields(buf, header, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        for i in 0..header.fields.len() {
+            let field = header.fields[i];
+            process_reserved_header(header, &field, &buf)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_headers_are_stored_as_typed_values() {
+        let mut header = HTTPHeader::new();
+        let buf = b"\
+        Host: localhost\r\n\
+        Content-Length: 42\r\n\
+        Transfer-Encoding: gzip\r\n\
+        transfer-encoding: chunked\r\n\
+        Connection: Upgrade, close\r\n\
+        Expect: 100-continue\r\n\
+        Upgrade: websocket\r\n\
+        TE: trailers\r\n\
+        Content-Type: multipart/form-data; boundary=\"abc def\"\r\n\
+        \r\n";
+        process_all_fields(buf, &mut header).unwrap();
+        assert_eq!(header.host.unwrap().value(buf), Ok("localhost"));
+        assert_eq!(header.content_length, Some(42));
+        assert_eq!(
+            header.transfer_encoding,
+            [TransferCoding::Gzip, TransferCoding::Chunked]
+        );
+        assert!(header.is_chunked());
+        assert!(!header.is_keep_alive());
+        assert_eq!(header.expect, Some(Expectation::Continue));
+        assert_eq!(header.upgrade.unwrap().value(buf), Ok("websocket"));
+        assert!(header.te_trailers);
+        let content_type = header.content_type.unwrap();
+        assert_eq!(content_type.essence(buf), b"multipart/form-data");
+        assert_eq!(
+            &content_type.parameter(buf, "boundary").unwrap()[..],
+            b"abc def"
+        );
+    }
+
+    #[test]
+    fn malformed_reserved_headers_should_failed() {
+        let cases: [&[u8]; 11] = [
+            b"Host: user@localhost\r\n\r\n",
+            b"Host: localhost:http\r\n\r\n",
+            b"Host: localhost\r\nHost: localhost\r\n\r\n",
+            b"Content-Length: 4a\r\n\r\n",
+            b"Content-Length: -1\r\n\r\n",
+            b"Transfer-Encoding: chunked x\r\n\r\n",
+            b"Connection: \"close\"\r\n\r\n",
+            b"Expect: ,\r\n\r\n",
+            b"Upgrade: HTTP/\r\n\r\n",
+            b"TE: trailers;q\r\n\r\n",
+            b"Content-Type: text\r\n\r\n",
+        ];
+        for buf in cases {
+            let mut header = HTTPHeader::new();
+            assert!(
+                process_all_fields(buf, &mut header).is_err(),
+                "{}",
+                String::from_utf8_lossy(buf)
+            );
+        }
+    }
+
+    fn validate_request(buf: &[u8], version: HttpVersion) -> Result<(), RashinErr> {
+        let mut header = HTTPHeader::new();
+        header.version = version;
+        process_all_fields(buf, &mut header)?;
+        validate_framing(&header)
+    }
+
+    #[test]
+    fn chunked_request_is_accepted() {
+        let buf = b"Transfer-Encoding: chunked\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_ok());
+        let buf = b"Content-Length: 10\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_0).is_ok());
+    }
+
+    #[test]
+    fn smuggling_duplicate_content_length() {
+        let buf = b"Content-Length: 10\r\nContent-Length: 5\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+        let buf = b"Content-Length: 10\r\nContent-Length: 10\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+        let buf = b"Content-Length: 10, 10\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+    }
+
+    #[test]
+    fn smuggling_content_length_with_transfer_encoding() {
+        let buf = b"Content-Length: 10\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+        let buf = b"Transfer-Encoding: chunked\r\nContent-Length: 10\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+    }
+
+    #[test]
+    fn smuggling_chunked_is_not_final() {
+        let buf = b"Transfer-Encoding: chunked, gzip\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+        let buf = b"Transfer-Encoding: chunked\r\nTransfer-Encoding: identity\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+        let buf = b"Transfer-Encoding: chunked, chunked\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+        let buf = b"Transfer-Encoding: xchunked\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+    }
+
+    #[test]
+    fn smuggling_empty_transfer_encoding() {
+        let buf = b"Content-Length: 10\r\nTransfer-Encoding:\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+        let buf = b"Content-Length: 10\r\nTransfer-Encoding: ,\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_1).is_err());
+    }
+
+    #[test]
+    fn smuggling_transfer_encoding_in_http_1_0() {
+        let buf = b"Transfer-Encoding: chunked\r\n\r\n";
+        assert!(validate_request(buf, HttpVersion::HTTP_1_0).is_err());
+    }
+
+    #[test]
+    fn smuggling_space_before_colon() {
+        let config = ParserConfig::default();
+        for buf in [
+            &b"Transfer-Encoding : chunked\r\n\r\n"[..],
+            &b"Content-Length\t: 10\r\n\r\n"[..],
+        ] {
+            let mut header = HTTPHeader::new();
+            let result = parse_all_fields(buf, &mut header, &config);
+            assert!(matches!(result, ParseResult::Error));
+        }
+    }
+}
@@ -1,7 +1,13 @@
 use std::io::Cursor;
 
+use memchr::memchr;
+
+use super::config::ParserConfig;
 use super::http_interface::{HTTPHeader, ParseResult};
-use super::parse_utility::{read_byte, ReadResult};
+use super::parse_request_target::parse_request_target;
+use super::parse_utility::{
+    advance, all_target_char, all_tchar, is_tchar, read_byte, remaining, ReadResult,
+};
 
 #[derive(Clone, Debug)]
 pub enum RequestLineState {
@@ -14,19 +20,20 @@ pub enum RequestLineState {
 
 /// RequestLineの実装
 /// リエントラントにするよう実装する。
+/// リクエストラインの長さがconfig.max_request_line_sizeを超えた場合はTooLargeを返す。
+/// 長さはheader.request_startから数えるので, 先頭の空行も含まれる。
 ///
-/// TODO: read周りが冗長なのでユーティリティ関数を作る
-/// TODO: HTTP1.1しかパースできないのでもう少し汎用的にする
 /// TODO: パスの中身をしっかり検証していない
 pub fn parse_http_request_line<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
     header: &mut HTTPHeader,
     mut state: RequestLineState,
+    config: &ParserConfig,
 ) -> ParseResult<RequestLineState> {
     loop {
         let result = match state {
             RequestLineState::Start => {
-                let result = parse_start(cursor, header);
+                let result = parse_start(cursor, header, config);
                 if let ParseResult::Ok(next_state) = &result {
                     state = next_state.clone();
                 }
@@ -53,12 +60,14 @@ pub fn parse_http_request_line<T: AsRef<[u8]>>(
                 }
                 result
             }
-            RequestLineState::End => {
-                let result = parse_end(cursor, header);
-                result
-            }
+            RequestLineState::End => parse_end(cursor, header, config),
         };
 
+        // 先頭の空行も含めないと, 空行だけでバッファを埋められてしまう
+        if cursor.position() as usize - header.request_start > config.max_request_line_size {
+            return ParseResult::TooLarge;
+        }
+
         match result {
             ParseResult::Again(state) => {
                 return ParseResult::Again(state);
@@ -67,8 +76,12 @@ pub fn parse_http_request_line<T: AsRef<[u8]>>(
                 return ParseResult::Error;
             }
             ParseResult::Complete => {
+                header.header_start = cursor.position() as usize;
                 return ParseResult::Complete;
             }
+            ParseResult::TooLarge => {
+                return ParseResult::TooLarge;
+            }
             ParseResult::Ok(_) => {
                 continue;
             }
@@ -76,19 +89,27 @@ pub fn parse_http_request_line<T: AsRef<[u8]>>(
     }
 }
 
+/// リクエストラインの先頭を読み込む。
+/// config.allow_leading_empty_linesが有効な場合はリクエストラインの前の空行を読み飛ばす。
 fn parse_start<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
     header: &mut HTTPHeader,
+    config: &ParserConfig,
 ) -> ParseResult<RequestLineState> {
     loop {
         match read_byte(cursor) {
             ReadResult::Ok(c) => {
                 if c == b'\r' || c == b'\n' {
+                    if !config.allow_leading_empty_lines {
+                        return ParseResult::Error;
+                    }
                     continue;
-                } else {
-                    header.method_start = cursor.position() as usize - 1;
-                    return ParseResult::Ok(RequestLineState::Method);
                 }
+                if !is_tchar(c) {
+                    return ParseResult::Error;
+                }
+                header.method_start = cursor.position() as usize - 1;
+                return ParseResult::Ok(RequestLineState::Method);
             }
             ReadResult::Again => {
                 return ParseResult::Again(RequestLineState::Start);
@@ -98,55 +119,76 @@ fn parse_start<T: AsRef<[u8]>>(
     }
 }
 
+/// methodをパースする。
+/// methodはtokenであり、tchar以外の文字が含まれる場合はエラーとする。
+/// 区切りのSPをmemchrでまとめて探し、その手前までを一度に検証する。
+///
+/// method = token
+/// token = 1*tchar
 fn parse_method<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
     header: &mut HTTPHeader,
 ) -> ParseResult<RequestLineState> {
-    loop {
-        match read_byte(cursor) {
-            ReadResult::Ok(c) => {
-                if c == b' ' {
-                    header.method_end = cursor.position() as usize - 1;
-                    header.path_start = cursor.position() as usize;
-                    return ParseResult::Ok(RequestLineState::Path);
-                }
-            }
-            ReadResult::Again => {
-                return ParseResult::Again(RequestLineState::Method);
+    let rest = remaining(cursor);
+    match memchr(b' ', rest) {
+        Some(n) => {
+            if !all_tchar(&rest[..n]) {
+                return ParseResult::Error;
             }
-            ReadResult::Err => {
+            advance(cursor, n + 1);
+            header.method_end = cursor.position() as usize - 1;
+            header.target_start = cursor.position() as usize;
+            ParseResult::Ok(RequestLineState::Path)
+        }
+        None => {
+            if !all_tchar(rest) {
                 return ParseResult::Error;
             }
+            advance(cursor, rest.len());
+            ParseResult::Again(RequestLineState::Method)
         }
     }
 }
 
+/// request-targetをパースする。
+/// SPまでを読み込んだ後に、request-targetの形式を判定して構成要素に分解する。
 fn parse_path<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
     header: &mut HTTPHeader,
 ) -> ParseResult<RequestLineState> {
-    loop {
-        match read_byte(cursor) {
-            ReadResult::Ok(c) => {
-                if c == b' ' {
-                    header.path_end = cursor.position() as usize - 1;
-                    header.protocol_start = cursor.position() as usize;
-                    return ParseResult::Ok(RequestLineState::Protocol);
-                }
-                if !c.is_ascii_graphic() {
-                    return ParseResult::Error;
-                }
+    let rest = remaining(cursor);
+    match memchr(b' ', rest) {
+        Some(n) => {
+            if !all_target_char(&rest[..n]) {
+                return ParseResult::Error;
             }
-            ReadResult::Again => {
-                return ParseResult::Again(RequestLineState::Path);
+            advance(cursor, n + 1);
+            header.target_end = cursor.position() as usize - 1;
+            header.protocol_start = cursor.position() as usize;
+            if !parse_request_target(cursor.get_ref().as_ref(), header) {
+                return ParseResult::Error;
             }
-            ReadResult::Err => {
+            ParseResult::Ok(RequestLineState::Protocol)
+        }
+        None => {
+            if !all_target_char(rest) {
                 return ParseResult::Error;
             }
+            advance(cursor, rest.len());
+            ParseResult::Again(RequestLineState::Path)
         }
     }
 }
 
+/// HTTP-versionをパースする。
+/// メジャーバージョン・マイナーバージョンともに1桁の数字であれば受け付け、
+/// サポートしているかどうかの判断は呼び出し側に任せる。
+///
+/// HTTP-version = HTTP-name "/" DIGIT "." DIGIT
+/// HTTP-name = %s"HTTP"
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9112#name-http-version
 fn parse_protocol<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
     header: &mut HTTPHeader,
@@ -165,35 +207,16 @@ fn parse_protocol<T: AsRef<[u8]>>(
 
         let offset = cursor.position() as usize - header.protocol_start - 1;
         match offset {
-            0 => {
-                if c != b'H' {
-                    return ParseResult::Error;
-                }
-            }
-            1 => {
-                if c != b'T' {
-                    return ParseResult::Error;
-                }
-            }
-            2 => {
-                if c != b'T' {
-                    return ParseResult::Error;
-                }
-            }
-            3 => {
-                if c != b'P' {
-                    return ParseResult::Error;
-                }
-            }
-            4 => {
-                if c != b'/' {
+            0..=4 => {
+                if c != b"HTTP/"[offset] {
                     return ParseResult::Error;
                 }
             }
             5 => {
-                if c != b'1' {
+                if !c.is_ascii_digit() {
                     return ParseResult::Error;
                 }
+                header.version.major = c - b'0';
             }
             6 => {
                 if c != b'.' {
@@ -201,9 +224,10 @@ fn parse_protocol<T: AsRef<[u8]>>(
                 }
             }
             7 => {
-                if c != b'1' {
+                if !c.is_ascii_digit() {
                     return ParseResult::Error;
                 }
+                header.version.minor = c - b'0';
                 // Success to parse
                 header.protocol_end = cursor.position() as usize;
                 return ParseResult::Ok(RequestLineState::End);
@@ -215,10 +239,12 @@ fn parse_protocol<T: AsRef<[u8]>>(
     }
 }
 
-/// CR LF またｈは LF で終わることを確認する
+/// CR LF または LF で終わることを確認する。
+/// LFだけの改行はconfig.allow_bare_lfが有効な場合のみ受け付ける。
 fn parse_end<T: AsRef<[u8]>>(
     cursor: &mut Cursor<T>,
-    header: &mut HTTPHeader,
+    _header: &mut HTTPHeader,
+    config: &ParserConfig,
 ) -> ParseResult<RequestLineState> {
     let read_result = read_byte(cursor);
     let c1 = match read_result {
@@ -232,12 +258,17 @@ fn parse_end<T: AsRef<[u8]>>(
     };
 
     if c1 == b'\n' {
+        if !config.allow_bare_lf {
+            return ParseResult::Error;
+        }
         return ParseResult::Complete;
     }
     if c1 == b'\r' {
         let c2 = match read_byte(cursor) {
             ReadResult::Ok(c) => c,
             ReadResult::Again => {
+                // 再開時にCRから読み直せるように戻しておく
+                cursor.set_position(cursor.position() - 1);
                 return ParseResult::Again(RequestLineState::End);
             }
             ReadResult::Err => {
@@ -254,21 +285,25 @@ fn parse_end<T: AsRef<[u8]>>(
 
 #[cfg(test)]
 mod tests {
-    use std::io::{SeekFrom, Seek};
+    use std::io::{Seek, SeekFrom};
 
-    use bytes::Bytes;
     use super::*;
+    use crate::http::http_interface::{HttpVersion, Method, RequestTarget};
+    use crate::http::uri::PathMode;
+    use bytes::Bytes;
 
     #[test]
     fn get_request_for_root() {
         let buf = Bytes::from("GET / HTTP/1.1\r\n");
         let mut cursor = Cursor::new(&buf);
         let mut header = HTTPHeader::new();
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
         assert!(matches!(result, ParseResult::Complete));
-        assert_eq!(header.method(&buf), "GET");
-        assert_eq!(header.path(&buf), "/");
-        assert_eq!(header.protocol(&buf), "HTTP/1.1");
+        assert_eq!(header.method(&buf), Method::Get);
+        assert_eq!(header.path(&buf), b"/");
+        assert_eq!(header.protocol(&buf), b"HTTP/1.1");
     }
 
     #[test]
@@ -276,11 +311,13 @@ mod tests {
         let buf = Bytes::from("GET /index.html HTTP/1.1\r\n");
         let mut cursor = Cursor::new(&buf);
         let mut header = HTTPHeader::new();
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
         assert!(matches!(result, ParseResult::Complete));
-        assert_eq!(header.method(&buf), "GET");
-        assert_eq!(header.path(&buf), "/index.html");
-        assert_eq!(header.protocol(&buf), "HTTP/1.1");
+        assert_eq!(header.method(&buf), Method::Get);
+        assert_eq!(header.path(&buf), b"/index.html");
+        assert_eq!(header.protocol(&buf), b"HTTP/1.1");
     }
 
     #[test]
@@ -288,11 +325,13 @@ mod tests {
         let buf = Bytes::from("GET / HTTP/1.1\n");
         let mut cursor = Cursor::new(&buf);
         let mut header = HTTPHeader::new();
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
         assert!(matches!(result, ParseResult::Complete));
-        assert_eq!(header.method(&buf), "GET");
-        assert_eq!(header.path(&buf), "/");
-        assert_eq!(header.protocol(&buf), "HTTP/1.1");
+        assert_eq!(header.method(&buf), Method::Get);
+        assert_eq!(header.path(&buf), b"/");
+        assert_eq!(header.protocol(&buf), b"HTTP/1.1");
     }
 
     #[test]
@@ -300,11 +339,439 @@ mod tests {
         let buf = Bytes::from("\r\n\rGET / HTTP/1.1\n");
         let mut cursor = Cursor::new(&buf);
         let mut header = HTTPHeader::new();
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.method(&buf), Method::Get);
+        assert_eq!(header.path(&buf), b"/");
+        assert_eq!(header.protocol(&buf), b"HTTP/1.1");
+    }
+
+    #[test]
+    fn get_request_with_http_1_0() {
+        let buf = Bytes::from("GET / HTTP/1.0\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.protocol(&buf), b"HTTP/1.0");
+        assert_eq!(header.version, HttpVersion::HTTP_1_0);
+        assert!(!header.is_keep_alive());
+    }
+
+    #[test]
+    fn get_request_with_unsupported_major_version() {
+        let buf = Bytes::from("GET / HTTP/2.0\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.version, HttpVersion::new(2, 0));
+        assert!(!header.version.is_supported());
+    }
+
+    #[test]
+    fn multi_digit_version_should_failed() {
+        let buf = Bytes::from("GET / HTTP/1.10\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Error));
+    }
+
+    #[test]
+    fn lowercase_protocol_name_should_failed() {
+        let buf = Bytes::from("GET / http/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Error));
+    }
+
+    #[test]
+    fn extension_method_request() {
+        let buf = Bytes::from("PROPFIND / HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.method(&buf), Method::Extension(b"PROPFIND"));
+    }
+
+    #[test]
+    fn method_is_case_sensitive() {
+        let buf = Bytes::from("get / HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.method(&buf), Method::Extension(b"get"));
+    }
+
+    #[test]
+    fn non_tchar_method_should_failed() {
+        for buf in [
+            "GE\x01T / HTTP/1.1\r\n",
+            "GE(T / HTTP/1.1\r\n",
+            "@GET / HTTP/1.1\r\n",
+        ] {
+            let buf = Bytes::from(buf);
+            let mut cursor = Cursor::new(&buf);
+            let mut header = HTTPHeader::new();
+            let config = ParserConfig::default();
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(result, ParseResult::Error));
+        }
+    }
+
+    #[test]
+    fn origin_form_with_query() {
+        let buf = Bytes::from("GET /search?q=rust&page=2 HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.target, RequestTarget::Origin);
+        assert_eq!(header.target(&buf), b"/search?q=rust&page=2");
+        assert_eq!(header.path(&buf), b"/search");
+        assert_eq!(header.query(&buf), Some(&b"q=rust&page=2"[..]));
+        assert_eq!(header.scheme(&buf), None);
+    }
+
+    #[test]
+    fn absolute_form_request() {
+        let buf = Bytes::from("GET http://example.com:8080/index.html?x=1 HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.target, RequestTarget::Absolute);
+        assert_eq!(header.scheme(&buf), Some(&b"http"[..]));
+        assert_eq!(header.authority(&buf), Some(&b"example.com:8080"[..]));
+        assert_eq!(header.path(&buf), b"/index.html");
+        assert_eq!(header.query(&buf), Some(&b"x=1"[..]));
+    }
+
+    #[test]
+    fn absolute_form_without_path() {
+        let buf = Bytes::from("GET http://example.com HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.authority(&buf), Some(&b"example.com"[..]));
+        assert_eq!(header.path(&buf), b"");
+        assert_eq!(header.query(&buf), None);
+    }
+
+    #[test]
+    fn absolute_form_with_userinfo_should_failed() {
+        for buf in [
+            "GET http://user@example.com/ HTTP/1.1\r\n",
+            "GET http:/example.com/ HTTP/1.1\r\n",
+            "GET 1http://example.com/ HTTP/1.1\r\n",
+            "GET http:///index.html HTTP/1.1\r\n",
+            "GET http://256.0.0.1/ HTTP/1.1\r\n",
+            "GET http://example.com:80:80/ HTTP/1.1\r\n",
+            "GET http://[::1/ HTTP/1.1\r\n",
+        ] {
+            let buf = Bytes::from(buf);
+            let mut cursor = Cursor::new(&buf);
+            let mut header = HTTPHeader::new();
+            let config = ParserConfig::default();
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(result, ParseResult::Error));
+        }
+    }
+
+    #[test]
+    fn authority_form_request() {
+        let buf = Bytes::from("CONNECT example.com:443 HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.target, RequestTarget::Authority);
+        assert_eq!(header.authority(&buf), Some(&b"example.com:443"[..]));
+        assert_eq!(header.path(&buf), b"");
+    }
+
+    #[test]
+    fn authority_form_must_be_used_only_with_connect() {
+        for buf in [
+            "CONNECT example.com HTTP/1.1\r\n",
+            "CONNECT /index.html HTTP/1.1\r\n",
+            "GET example.com:443 HTTP/1.1\r\n",
+        ] {
+            let buf = Bytes::from(buf);
+            let mut cursor = Cursor::new(&buf);
+            let mut header = HTTPHeader::new();
+            let config = ParserConfig::default();
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(result, ParseResult::Error));
+        }
+    }
+
+    #[test]
+    fn asterisk_form_request() {
+        let buf = Bytes::from("OPTIONS * HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.target, RequestTarget::Asterisk);
+        assert_eq!(header.path(&buf), b"*");
+
+        let buf = Bytes::from("GET * HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Error));
+    }
+
+    #[test]
+    fn decode_path_and_query() {
+        let buf = Bytes::from("GET /hello%20world/%E3%81%82?name=a+b&x=%2F HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.path(&buf), b"/hello%20world/%E3%81%82");
+        assert_eq!(&header.decoded_path(&buf)[..], "/hello world/あ".as_bytes());
+
+        let pairs: Vec<_> = header.query_pairs(&buf).collect();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(
+            (&pairs[0].0[..], &pairs[0].1[..]),
+            (&b"name"[..], &b"a b"[..])
+        );
+        assert_eq!((&pairs[1].0[..], &pairs[1].1[..]), (&b"x"[..], &b"/"[..]));
+    }
+
+    #[test]
+    fn fragment_and_malformed_escape_should_failed() {
+        for buf in [
+            "GET /index.html#top HTTP/1.1\r\n",
+            "GET /a%2 HTTP/1.1\r\n",
+            "GET /a%zz HTTP/1.1\r\n",
+            "GET /?q=%G0 HTTP/1.1\r\n",
+        ] {
+            let buf = Bytes::from(buf);
+            let mut cursor = Cursor::new(&buf);
+            let mut header = HTTPHeader::new();
+            let config = ParserConfig::default();
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(result, ParseResult::Error));
+        }
+    }
+
+    #[test]
+    fn normalize_traversal_path() {
+        for (request, expected) in [
+            ("GET /../../etc/passwd HTTP/1.1\r\n", "/etc/passwd"),
+            ("GET /%2e%2e/%2E%2E/etc/passwd HTTP/1.1\r\n", "/etc/passwd"),
+            (
+                "GET /static//css/./../js/app.js HTTP/1.1\r\n",
+                "/static/js/app.js",
+            ),
+            ("GET http://example.com HTTP/1.1\r\n", "/"),
+        ] {
+            let buf = Bytes::from(request);
+            let mut cursor = Cursor::new(&buf);
+            let mut header = HTTPHeader::new();
+            let config = ParserConfig::default();
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(result, ParseResult::Complete));
+            let path = header.normalized_path(&buf, PathMode::Strict).unwrap();
+            assert_eq!(&path[..], expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn strict_path_rejects_encoded_slash_and_nul() {
+        for request in [
+            "GET /..%2F..%2Fetc/passwd HTTP/1.1\r\n",
+            "GET /index.html%00.png HTTP/1.1\r\n",
+        ] {
+            let buf = Bytes::from(request);
+            let mut cursor = Cursor::new(&buf);
+            let mut header = HTTPHeader::new();
+            let config = ParserConfig::default();
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(result, ParseResult::Complete));
+            assert!(header.normalized_path(&buf, PathMode::Strict).is_none());
+            assert!(header.normalized_path(&buf, PathMode::Lenient).is_some());
+        }
+    }
+
+    #[test]
+    fn too_long_request_line() {
+        let config = ParserConfig {
+            max_request_line_size: 32,
+            ..ParserConfig::default()
+        };
+        let buf = Bytes::from("GET /0123456789012345 HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+
+        let buf = Bytes::from("GET /01234567890123456 HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::TooLarge));
+
+        // 改行が届く前でも上限を超えた時点でTooLargeを返す
+        let buf = Bytes::from("GET /0123456789012345678901234567890");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::TooLarge));
+    }
+
+    #[test]
+    fn resume_from_each_split_point() {
+        let full = "GET /index.html?q=1 HTTP/1.1\r\n";
+        let config = ParserConfig::default();
+        for split in 0..full.len() {
+            let mut header = HTTPHeader::new();
+            let buf = Bytes::from(&full[..split]);
+            let mut cursor = Cursor::new(&buf);
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            let state = match result {
+                ParseResult::Again(state) => state,
+                _ => panic!("split at {} should be incomplete", split),
+            };
+            let pos = cursor.position();
+
+            let buf = Bytes::from(full);
+            let mut cursor = Cursor::new(&buf);
+            cursor.set_position(pos);
+            let result = parse_http_request_line(&mut cursor, &mut header, state, &config);
+            assert!(matches!(result, ParseResult::Complete));
+            assert_eq!(header.method(&buf), Method::Get);
+            assert_eq!(header.path(&buf), b"/index.html");
+            assert_eq!(header.query(&buf), Some(&b"q=1"[..]));
+            assert_eq!(header.version, HttpVersion::HTTP_1_1);
+        }
+    }
+
+    #[test]
+    fn strict_profile_rejects_bare_lf_and_leading_empty_lines() {
+        let config = ParserConfig::strict();
+        for buf in ["GET / HTTP/1.1\n", "\r\nGET / HTTP/1.1\r\n"] {
+            let buf = Bytes::from(buf);
+            let mut cursor = Cursor::new(&buf);
+            let mut header = HTTPHeader::new();
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(result, ParseResult::Error));
+        }
+
+        let buf = Bytes::from("GET / HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+    }
+
+    #[test]
+    fn lenient_profile_accepts_bare_lf_and_leading_empty_lines() {
+        let config = ParserConfig::lenient();
+        let buf = Bytes::from("\r\n\nGET / HTTP/1.1\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        assert_eq!(header.method(&buf), Method::Get);
+    }
+
+    #[test]
+    fn leading_empty_lines_count_toward_request_line_size() {
+        let config = ParserConfig {
+            max_request_line_size: 32,
+            ..ParserConfig::default()
+        };
+        let buf = Bytes::from("\r\n\r\nGET /012345678901 HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+
+        let buf = Bytes::from("\r\n\r\nGET /0123456789012 HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::TooLarge));
+
+        // 空行だけが続く場合も上限を超えた時点でTooLargeを返す
+        let buf = Bytes::from("\r\n".repeat(17));
+        let mut cursor = Cursor::new(&buf);
+        let mut header = HTTPHeader::new();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::TooLarge));
+    }
+
+    #[test]
+    fn strict_profile_resumes_between_cr_and_lf() {
+        let config = ParserConfig::strict();
+        let mut header = HTTPHeader::new();
+        let buf = Bytes::from("GET / HTTP/1.1\r");
+        let mut cursor = Cursor::new(&buf);
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Again(RequestLineState::End)));
+        let pos = cursor.position();
+
+        let buf = Bytes::from("GET / HTTP/1.1\r\n");
+        let mut cursor = Cursor::new(&buf);
+        cursor.set_position(pos);
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::End, &config);
         assert!(matches!(result, ParseResult::Complete));
-        assert_eq!(header.method(&buf), "GET");
-        assert_eq!(header.path(&buf), "/");
-        assert_eq!(header.protocol(&buf), "HTTP/1.1");
     }
 
     #[test]
@@ -312,7 +779,9 @@ mod tests {
         let buf = Bytes::from("GET   /   HTTP/1.1\n");
         let mut cursor = Cursor::new(&buf);
         let mut header = HTTPHeader::new();
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
         assert!(matches!(result, ParseResult::Error));
     }
 
@@ -321,7 +790,9 @@ mod tests {
         let buf = Bytes::from("GET HTTP/1.1\n");
         let mut cursor = Cursor::new(&buf);
         let mut header = HTTPHeader::new();
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
         assert!(matches!(result, ParseResult::Error));
     }
 
@@ -330,7 +801,9 @@ mod tests {
         let buf = Bytes::from("GET / SMTP/1.1\n");
         let mut cursor = Cursor::new(&buf);
         let mut header = HTTPHeader::new();
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
         assert!(matches!(result, ParseResult::Error));
     }
 
@@ -339,7 +812,9 @@ mod tests {
         let buf = Bytes::from("GET / HTTP/1.1xxx\n");
         let mut cursor = Cursor::new(&buf);
         let mut header = HTTPHeader::new();
-        let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+        let config = ParserConfig::default();
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
         assert!(matches!(result, ParseResult::Error));
     }
 
@@ -347,12 +822,17 @@ mod tests {
     fn continue_from_previous_parse_start() {
         let mut pos: u64 = 0;
         let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         {
             let buf = Bytes::from("");
             let mut cursor = Cursor::new(buf);
             cursor.seek(SeekFrom::Start(pos)).unwrap();
-            let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
-            assert!(matches!(result, ParseResult::Again(RequestLineState::Start)));
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(
+                result,
+                ParseResult::Again(RequestLineState::Start)
+            ));
             pos = cursor.position();
         }
 
@@ -360,7 +840,8 @@ mod tests {
             let buf = Bytes::from("GET / HTTP/1.1\r\n");
             let mut cursor = Cursor::new(buf);
             cursor.seek(SeekFrom::Start(pos)).unwrap();
-            let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
             assert!(matches!(result, ParseResult::Complete));
         }
     }
@@ -369,12 +850,17 @@ mod tests {
     fn continue_from_previous_parse_method() {
         let mut pos: u64 = 0;
         let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         {
             let buf = Bytes::from("G");
             let mut cursor = Cursor::new(buf);
             cursor.seek(SeekFrom::Start(pos)).unwrap();
-            let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
-            assert!(matches!(result, ParseResult::Again(RequestLineState::Method)));
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(
+                result,
+                ParseResult::Again(RequestLineState::Method)
+            ));
             pos = cursor.position();
         }
 
@@ -382,7 +868,12 @@ mod tests {
             let buf = Bytes::from("GET / HTTP/1.1\r\n");
             let mut cursor = Cursor::new(buf);
             cursor.seek(SeekFrom::Start(pos)).unwrap();
-            let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Method);
+            let result = parse_http_request_line(
+                &mut cursor,
+                &mut header,
+                RequestLineState::Method,
+                &config,
+            );
             assert!(matches!(result, ParseResult::Complete));
         }
     }
@@ -391,11 +882,13 @@ mod tests {
     fn continue_from_previous_parse_path() {
         let mut pos: u64 = 0;
         let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         {
             let buf = Bytes::from("GET /test");
             let mut cursor = Cursor::new(buf);
             cursor.seek(SeekFrom::Start(pos)).unwrap();
-            let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
             assert!(matches!(result, ParseResult::Again(RequestLineState::Path)));
             pos = cursor.position();
         }
@@ -404,7 +897,8 @@ mod tests {
             let buf = Bytes::from("GET /test HTTP/1.1\r\n");
             let mut cursor = Cursor::new(buf);
             cursor.seek(SeekFrom::Start(pos)).unwrap();
-            let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Path);
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Path, &config);
             assert!(matches!(result, ParseResult::Complete));
         }
     }
@@ -413,12 +907,17 @@ mod tests {
     fn continue_from_previous_parse_protocol() {
         let mut pos: u64 = 0;
         let mut header = HTTPHeader::new();
+        let config = ParserConfig::default();
         {
             let buf = Bytes::from("GET / HTTP");
             let mut cursor = Cursor::new(buf);
             cursor.seek(SeekFrom::Start(pos)).unwrap();
-            let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start);
-            assert!(matches!(result, ParseResult::Again(RequestLineState::Protocol)));
+            let result =
+                parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+            assert!(matches!(
+                result,
+                ParseResult::Again(RequestLineState::Protocol)
+            ));
             pos = cursor.position();
         }
 
@@ -426,7 +925,12 @@ mod tests {
             let buf = Bytes::from("GET / HTTP/1.1\r\n");
             let mut cursor = Cursor::new(buf);
             cursor.seek(SeekFrom::Start(pos)).unwrap();
-            let result = parse_http_request_line(&mut cursor, &mut header, RequestLineState::Protocol);
+            let result = parse_http_request_line(
+                &mut cursor,
+                &mut header,
+                RequestLineState::Protocol,
+                &config,
+            );
             assert!(matches!(result, ParseResult::Complete));
         }
     }
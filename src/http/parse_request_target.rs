@@ -0,0 +1,162 @@
+//! request-targetの分類と構成要素への分解
+//!
+//! request-target = origin-form / absolute-form / authority-form / asterisk-form
+//!
+//! References:
+//! https://www.rfc-editor.org/rfc/rfc9112#name-request-target
+use super::http_interface::{HTTPHeader, Method, RequestTarget};
+use super::uri::{is_valid_percent_encoding, Host};
+
+/// `header.target_start..header.target_end`にあるrequest-targetを分類し、
+/// scheme, authority, path, queryの位置をheaderに記録する。
+/// 各要素はbuffer上の位置として保持するのでコピーは発生しない。
+///
+/// * CONNECTの場合はauthority-formのみを受け付ける
+/// * asterisk-formはOPTIONSの場合のみ受け付ける
+/// * "/"から始まる場合はorigin-form, それ以外はabsolute-formとして扱う
+///
+/// request-targetにfragmentは含まれないので"#"はエラーとし、
+/// 不正なpercent-encodingもここで弾いておく。
+pub fn parse_request_target(buffer: &[u8], header: &mut HTTPHeader) -> bool {
+    let start = header.target_start;
+    let end = header.target_end;
+    if start >= end {
+        return false;
+    }
+    let target = &buffer[start..end];
+    if target.contains(&b'#') || !is_valid_percent_encoding(target) {
+        return false;
+    }
+    let method = Method::from_bytes(&buffer[header.method_start..header.method_end]);
+
+    if method == Method::Connect {
+        return parse_authority_form(buffer, header);
+    }
+    match buffer[start] {
+        b'/' => {
+            header.target = RequestTarget::Origin;
+            split_path_and_query(buffer, start, end, header);
+            true
+        }
+        b'*' if end - start == 1 => {
+            if method != Method::Options {
+                return false;
+            }
+            header.target = RequestTarget::Asterisk;
+            header.path_start = start;
+            header.path_end = end;
+            true
+        }
+        _ => parse_absolute_form(buffer, header),
+    }
+}
+
+/// absolute-formをパースする。
+/// HTTPのリクエストではauthorityが必須なので、hier-partは"//"から始まるものに限る。
+///
+/// absolute-form = absolute-URI
+/// absolute-URI = scheme ":" hier-part [ "?" query ]
+/// hier-part = "//" authority path-abempty
+/// scheme = ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )
+fn parse_absolute_form(buffer: &[u8], header: &mut HTTPHeader) -> bool {
+    let start = header.target_start;
+    let end = header.target_end;
+
+    let colon = match buffer[start..end].iter().position(|&c| c == b':') {
+        Some(n) => start + n,
+        None => return false,
+    };
+    let scheme = &buffer[start..colon];
+    if scheme.is_empty() || !scheme[0].is_ascii_alphabetic() {
+        return false;
+    }
+    if !scheme
+        .iter()
+        .all(|&c| c.is_ascii_alphanumeric() || c == b'+' || c == b'-' || c == b'.')
+    {
+        return false;
+    }
+    if !buffer[colon + 1..end].starts_with(b"//") {
+        return false;
+    }
+
+    let authority_start = colon + 3;
+    let authority_end = buffer[authority_start..end]
+        .iter()
+        .position(|&c| c == b'/' || c == b'?')
+        .map_or(end, |n| authority_start + n);
+    let host = match parse_authority(buffer, authority_start, authority_end) {
+        Some(host) => host,
+        None => return false,
+    };
+
+    header.target = RequestTarget::Absolute;
+    header.scheme_start = start;
+    header.scheme_end = colon;
+    header.authority_start = authority_start;
+    header.authority_end = authority_end;
+    header.target_host = Some(host);
+    split_path_and_query(buffer, authority_end, end, header);
+    true
+}
+
+/// authority-formをパースする。CONNECTでのみ使われ、portは省略できない。
+///
+/// authority-form = uri-host ":" port
+fn parse_authority_form(buffer: &[u8], header: &mut HTTPHeader) -> bool {
+    let start = header.target_start;
+    let end = header.target_end;
+    let authority = &buffer[start..end];
+
+    let colon = match authority.iter().rposition(|&c| c == b':') {
+        Some(n) => n,
+        None => return false,
+    };
+    let port = &authority[colon + 1..];
+    if colon == 0 || port.is_empty() || !port.iter().all(|c| c.is_ascii_digit()) {
+        return false;
+    }
+    let host = match parse_authority(buffer, start, end) {
+        Some(host) => host,
+        None => return false,
+    };
+
+    header.target = RequestTarget::Authority;
+    header.authority_start = start;
+    header.authority_end = end;
+    header.target_host = Some(host);
+    header.path_start = end;
+    header.path_end = end;
+    true
+}
+
+/// authorityをuri-hostとportに分解する。
+/// RFC9110ではhttp(s)のURIにuserinfoを含めることは禁止されているので"@"はエラーとし、
+/// hostが空の場合もエラーとする。
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-http-related-uri-schemes
+fn parse_authority(buffer: &[u8], start: usize, end: usize) -> Option<Host> {
+    let host = Host::parse(&buffer[start..end], start)?;
+    if host.start == host.end {
+        return None;
+    }
+    Some(host)
+}
+
+/// `start..end`を最初の"?"でpathとqueryに分割する。
+fn split_path_and_query(buffer: &[u8], start: usize, end: usize, header: &mut HTTPHeader) {
+    match buffer[start..end].iter().position(|&c| c == b'?') {
+        Some(n) => {
+            header.path_start = start;
+            header.path_end = start + n;
+            header.has_query = true;
+            header.query_start = start + n + 1;
+            header.query_end = end;
+        }
+        None => {
+            header.path_start = start;
+            header.path_end = end;
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{Cursor, Read};
+use std::io::Cursor;
 
 pub enum ReadResult {
     Ok(u8),
@@ -6,15 +6,75 @@ pub enum ReadResult {
     Err,
 }
 
+/// cursorから1バイト読み込む。
+/// Cursor::readを経由するとバイトごとにスライスのコピーが発生するので, 直接バッファを参照する。
 pub fn read_byte<T: AsRef<[u8]>>(cursor: &mut Cursor<T>) -> ReadResult {
-    let mut b = [0; 1];
-    let size = cursor.read(&mut b);
+    let position = cursor.position();
+    let buf = cursor.get_ref().as_ref();
+    if position > buf.len() as u64 {
+        return ReadResult::Err;
+    }
+    match buf.get(position as usize) {
+        Some(&b) => {
+            cursor.set_position(position + 1);
+            ReadResult::Ok(b)
+        }
+        None => ReadResult::Again,
+    }
+}
+
+/// cursorの現在位置から末尾までの, まだ読み込んでいないデータを返す。
+/// memchrで区切り文字をまとめて探すための高速パスで使う。
+pub fn remaining<T: AsRef<[u8]>>(cursor: &Cursor<T>) -> &[u8] {
+    let buf = cursor.get_ref().as_ref();
+    let position = (cursor.position() as usize).min(buf.len());
+    &buf[position..]
+}
+
+/// cursorをnバイト進める
+pub fn advance<T: AsRef<[u8]>>(cursor: &mut Cursor<T>, n: usize) {
+    cursor.set_position(cursor.position() + n as u64);
+}
 
-    match size {
-        Ok(0) => ReadResult::Again,
-        Ok(1) => ReadResult::Ok(b[0]),
-        _ => ReadResult::Err,
+/// 256バイト分の文字クラスを事前に計算したテーブル。
+/// 1バイトずつ分岐するよりも, スライスをまとめて検証する場合に速い。
+const fn build_table(class: u8) -> [bool; 256] {
+    let mut table = [false; 256];
+    let mut i = 0;
+    while i < 256 {
+        let c = i as u8;
+        table[i] = match class {
+            TCHAR => is_tchar(c),
+            TARGET => c.is_ascii_graphic(),
+            FIELD_CONTENT => is_field_vchar(c) || c == b' ' || c == b'\t',
+            _ => false,
+        };
+        i += 1;
     }
+    table
+}
+
+const TCHAR: u8 = 0;
+const TARGET: u8 = 1;
+const FIELD_CONTENT: u8 = 2;
+
+static TCHAR_TABLE: [bool; 256] = build_table(TCHAR);
+static TARGET_TABLE: [bool; 256] = build_table(TARGET);
+static FIELD_CONTENT_TABLE: [bool; 256] = build_table(FIELD_CONTENT);
+
+/// 全てのバイトがtcharであるか
+pub fn all_tchar(bytes: &[u8]) -> bool {
+    bytes.iter().all(|&c| TCHAR_TABLE[c as usize])
+}
+
+/// 全てのバイトがrequest-targetに使える表示可能な文字であるか
+pub fn all_target_char(bytes: &[u8]) -> bool {
+    bytes.iter().all(|&c| TARGET_TABLE[c as usize])
+}
+
+/// 全てのバイトがfield-vchar, SP, HTABのいずれかであるか
+pub fn all_field_content(bytes: &[u8]) -> bool {
+    bytes.iter().all(|&c| FIELD_CONTENT_TABLE[c as usize])
 }
 
 /// Check if the given byte is a tchar.
@@ -27,21 +87,54 @@ pub fn read_byte<T: AsRef<[u8]>>(cursor: &mut Cursor<T>) -> ReadResult {
 /// Reference:
 /// https://www.rfc-editor.org/rfc/rfc9110#name-collected-abnf
 ///
-pub fn is_tchar(byte: u8) -> bool {
+pub const fn is_tchar(byte: u8) -> bool {
     if byte.is_ascii_alphanumeric() {
         return true;
     }
-    match byte {
-        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
-        | b'`' | b'|' | b'~' => true,
-        _ => false,
-    }
+    matches!(
+        byte,
+        b'!' | b'#'
+            | b'$'
+            | b'%'
+            | b'&'
+            | b'\''
+            | b'*'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~'
+    )
 }
 
-/// Check if the given byte is a tchar.
+/// Check if the given byte is a vchar.
 /// "vchar" is a chracter that can be displayed.
 /// Reference:
 /// https://www.rfc-editor.org/rfc/rfc9110#name-syntax-notation
-pub fn is_vchar(byte: u8) -> bool {
+pub const fn is_vchar(byte: u8) -> bool {
     byte.is_ascii_graphic()
 }
+
+/// Check if the given byte is a field-vchar.
+/// field values may contain obs-text (0x80-0xFF) in addition to vchar.
+///
+/// field-vchar = VCHAR / obs-text
+/// obs-text = %x80-FF
+/// Reference:
+/// https://www.rfc-editor.org/rfc/rfc9110#name-field-values
+pub const fn is_field_vchar(byte: u8) -> bool {
+    is_vchar(byte) || byte >= 0x80
+}
+
+/// 16進数の1文字を数値に変換する。HEXDIGでない場合はNoneを返す。
+pub fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
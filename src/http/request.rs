@@ -0,0 +1,227 @@
+//! ハンドラーに渡すリクエスト
+//!
+//! HTTPHeaderはバッファ上の位置しか持たないので, Requestはバッファと組にして値を参照できるようにする。
+//! 値はコピーせずにバッファの一部として返す。field-valueはobs-textを含むことがあるので,
+//! 文字列が必要な場合はUTF-8として解釈できるかどうかを呼び出し側で扱う。
+use std::borrow::Cow;
+use std::str::Utf8Error;
+
+use super::header_value::MediaType;
+use super::http_interface::{Field, HTTPHeader, HttpVersion, Method, RequestTarget};
+use super::uri::{PathMode, QueryPairs};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Request<'buf> {
+    header: &'buf HTTPHeader,
+    buffer: &'buf [u8],
+}
+
+impl<'buf> Request<'buf> {
+    /// bufferはheaderをパースしたバッファでなければならない
+    pub fn new(header: &'buf HTTPHeader, buffer: &'buf [u8]) -> Self {
+        Request { header, buffer }
+    }
+
+    /// パースした位置をそのまま参照する
+    pub fn header(&self) -> &'buf HTTPHeader {
+        self.header
+    }
+
+    pub fn method(&self) -> Method<'buf> {
+        self.header.method(self.buffer)
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        self.header.version
+    }
+
+    pub fn target_form(&self) -> RequestTarget {
+        self.header.target
+    }
+
+    /// request-target全体を返す
+    pub fn target(&self) -> &'buf [u8] {
+        self.header.target(self.buffer)
+    }
+
+    pub fn target_str(&self) -> Result<&'buf str, Utf8Error> {
+        std::str::from_utf8(self.target())
+    }
+
+    /// pct-encodedをデコードする前のpathを返す
+    pub fn path(&self) -> &'buf [u8] {
+        self.header.path(self.buffer)
+    }
+
+    pub fn path_str(&self) -> Result<&'buf str, Utf8Error> {
+        std::str::from_utf8(self.path())
+    }
+
+    /// pct-encodedをデコードしたpathを返す
+    pub fn decoded_path(&self) -> Cow<'buf, [u8]> {
+        self.header.decoded_path(self.buffer)
+    }
+
+    /// デコードしたpathからdot-segmentと重複した"/"を取り除いたものを返す
+    pub fn normalized_path(&self, mode: PathMode) -> Option<Cow<'buf, [u8]>> {
+        self.header.normalized_path(self.buffer, mode)
+    }
+
+    /// "?"以降のqueryを返す。"?"が無い場合はNoneを返す
+    pub fn query(&self) -> Option<&'buf [u8]> {
+        self.header.query(self.buffer)
+    }
+
+    pub fn query_str(&self) -> Result<Option<&'buf str>, Utf8Error> {
+        self.query().map(std::str::from_utf8).transpose()
+    }
+
+    pub fn query_pairs(&self) -> QueryPairs<'buf> {
+        self.header.query_pairs(self.buffer)
+    }
+
+    /// リクエストの対象となるauthorityを返す
+    pub fn authority(&self) -> Option<&'buf [u8]> {
+        self.header.authority(self.buffer)
+    }
+
+    /// 仮想ホストの選択に使う, 正規化したホスト名を返す
+    pub fn host(&self) -> Option<Cow<'buf, [u8]>> {
+        self.header.normalized_host(self.buffer)
+    }
+
+    /// nameに一致するヘッダーのうち最初に現れたものの値を返す。
+    /// nameは大文字・小文字を区別しない。
+    pub fn get(&self, name: &str) -> Option<&'buf [u8]> {
+        self.header
+            .get(name, self.buffer)
+            .map(|field| field.value_bytes(self.buffer))
+    }
+
+    /// getの値をUTF-8の文字列として返す
+    pub fn get_str(&self, name: &str) -> Result<Option<&'buf str>, Utf8Error> {
+        self.get(name).map(std::str::from_utf8).transpose()
+    }
+
+    /// nameに一致するヘッダーの値を受信した順に全て返す
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'buf [u8]> + 'a {
+        let buffer = self.buffer;
+        self.header
+            .get_all(name, buffer)
+            .map(move |field| field.value_bytes(buffer))
+    }
+
+    /// 受信した順にヘッダーの名前と値の組を返す
+    pub fn fields(&self) -> impl Iterator<Item = (&'buf [u8], &'buf [u8])> {
+        let buffer = self.buffer;
+        self.header
+            .fields
+            .iter()
+            .map(move |field| (field.name_bytes(buffer), field.value_bytes(buffer)))
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.header.content_length
+    }
+
+    pub fn content_type(&self) -> Option<MediaType> {
+        self.header.content_type
+    }
+
+    /// Content-Typeのtype/subtypeを返す
+    pub fn essence(&self) -> Option<&'buf [u8]> {
+        self.content_type()
+            .map(|media_type| media_type.essence(self.buffer))
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.header.is_chunked()
+    }
+
+    pub fn is_keep_alive(&self) -> bool {
+        self.header.is_keep_alive()
+    }
+
+    /// Fieldが位置を指しているバッファ
+    pub fn buffer(&self) -> &'buf [u8] {
+        self.buffer
+    }
+
+    /// fieldの値を返す。fieldはこのリクエストのヘッダーでなければならない
+    pub fn value(&self, field: &Field) -> &'buf [u8] {
+        field.value_bytes(self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::config::ParserConfig;
+    use crate::http::http_interface::ParseResult;
+    use crate::http::parse_request_header::{
+        parse_http_request_header, process_reserved_header, RequestHeaderState,
+    };
+    use crate::http::parse_request_line::{parse_http_request_line, RequestLineState};
+    use std::io::Cursor;
+
+    fn parse_request(buf: &[u8]) -> HTTPHeader {
+        let config = ParserConfig::default();
+        let mut header = HTTPHeader::new();
+        let mut cursor = Cursor::new(buf);
+        let result =
+            parse_http_request_line(&mut cursor, &mut header, RequestLineState::Start, &config);
+        assert!(matches!(result, ParseResult::Complete));
+        loop {
+            let mut field = Field::new();
+            let result = parse_http_request_header(
+                &mut cursor,
+                &mut header,
+                &mut field,
+                RequestHeaderState::Start,
+                &config,
+            );
+            assert!(matches!(result, ParseResult::Complete));
+            if field.is_separator {
+                return header;
+            }
+            process_reserved_header(&mut header, &field, &buf).unwrap();
+        }
+    }
+
+    #[test]
+    fn request_returns_bytes_from_buffer() {
+        let buf = b"POST /upload/a%20b?x=1 HTTP/1.1\r\n\
+Host: Example.com\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+Content-Length: 0\r\n\
+X-Tag: a\r\n\
+X-Tag: b\r\n\
+\r\n";
+        let header = parse_request(buf);
+        let request = Request::new(&header, buf);
+        assert_eq!(request.method(), Method::Post);
+        assert_eq!(request.version(), HttpVersion::HTTP_1_1);
+        assert_eq!(request.target(), b"/upload/a%20b?x=1");
+        assert_eq!(request.path(), b"/upload/a%20b");
+        assert_eq!(request.path_str(), Ok("/upload/a%20b"));
+        assert_eq!(&request.decoded_path()[..], b"/upload/a b");
+        assert_eq!(request.query_str(), Ok(Some("x=1")));
+        assert_eq!(request.host().as_deref(), Some(&b"example.com"[..]));
+        assert_eq!(request.get("host"), Some(&b"Example.com"[..]));
+        assert_eq!(request.get_str("X-Missing"), Ok(None));
+        assert_eq!(request.get_all("x-tag").collect::<Vec<_>>(), [b"a", b"b"]);
+        assert_eq!(request.fields().count(), 5);
+        assert_eq!(request.content_length(), Some(0));
+        assert_eq!(request.essence(), Some(&b"text/plain"[..]));
+    }
+
+    #[test]
+    fn non_utf8_value_does_not_panic() {
+        let buf = b"GET / HTTP/1.1\r\nHost: a\r\nX-Name: caf\xe9\r\n\r\n";
+        let header = parse_request(buf);
+        let request = Request::new(&header, buf);
+        assert_eq!(request.get("X-Name"), Some(&b"caf\xe9"[..]));
+        assert!(request.get_str("X-Name").is_err());
+        assert_eq!(request.get_str("Host"), Ok(Some("a")));
+    }
+}
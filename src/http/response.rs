@@ -0,0 +1,568 @@
+//! レスポンスの組み立てと送信用のバイト列への変換
+//!
+//! status-line = HTTP-version SP status-code SP [ reason-phrase ]
+//! HTTP-message = start-line CRLF *( field-line CRLF ) CRLF [ message-body ]
+//!
+//! 長さが分からないボディは, HTTP/1.1のクライアントにはchunkedで,
+//! HTTP/1.0のクライアントには接続を閉じることで終わりを示して送信する。
+//!
+//! References:
+//! https://www.rfc-editor.org/rfc/rfc9112#name-status-line
+//! https://www.rfc-editor.org/rfc/rfc9112#name-chunked-transfer-coding
+use std::borrow::Cow;
+
+use super::http_interface::HttpVersion;
+use super::parse_utility::{all_field_content, all_tchar};
+use super::status::StatusCode;
+use crate::error::RashinErr;
+
+/// 接続の状態によって決まり, シリアライズ時にレスポンスへ自動的に追加するヘッダーの値
+#[derive(Clone, Copy, Debug)]
+pub struct ResponseContext<'a> {
+    /// Dateヘッダーの値。Noneの場合は送信しない
+    pub date: Option<&'a [u8]>,
+    /// Serverヘッダーの値。Noneの場合は送信しない
+    pub server: Option<&'a str>,
+    /// リクエストのHTTPバージョン
+    pub version: HttpVersion,
+    /// レスポンスを送信した後に接続を維持するかどうか
+    pub keep_alive: bool,
+    /// HEADリクエストへのレスポンスかどうか。ヘッダーだけを送信し, ボディは送信しない
+    pub head: bool,
+}
+
+/// レスポンスボディ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResponseBody {
+    /// 長さが決まっているボディ。Content-Lengthを付けて送信する
+    Fixed(Vec<u8>),
+    /// 長さが分からないボディ。ヘッダーを送信した後にBodyWriterで書き込む。
+    Streaming,
+}
+
+/// ストリーミングするボディの終わりの示し方
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferMode {
+    /// chunked transfer codingで送信し, last-chunkで終わりを示す
+    Chunked,
+    /// そのまま送信し, 接続を閉じて終わりを示す
+    CloseDelimited,
+}
+
+/// サーバーが返すレスポンス。
+/// ヘッダーは追加した順に送信する。
+/// Content-LengthとTransfer-Encodingはボディからサーバーが決めるので, 追加しても送信しない。
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    /// newはステータスコードに対応する標準のreason-phraseを設定する
+    pub reason: Cow<'static, str>,
+    headers: Vec<(Cow<'static, str>, Vec<u8>)>,
+    pub body: ResponseBody,
+}
+
+impl Response {
+    /// ステータスコードに対応する標準のreason-phraseを持つレスポンスを作る
+    pub fn new(status: StatusCode) -> Self {
+        Response {
+            status,
+            reason: Cow::Borrowed(status.reason_phrase().unwrap_or("")),
+            headers: Vec::new(),
+            body: ResponseBody::Fixed(Vec::new()),
+        }
+    }
+
+    /// ヘッダーを追加する。同じ名前のヘッダーがあっても置き換えない。
+    /// field-nameがtokenでない場合や, field-valueに改行などを含む場合はエラーを返す。
+    pub fn add_header(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<(), RashinErr> {
+        let name = name.into();
+        let value = value.into();
+        if !is_valid_field(&name, &value) {
+            return Err(RashinErr::InvalidHeader("response"));
+        }
+        self.headers.push((name, value));
+        Ok(())
+    }
+
+    /// nameに一致するヘッダーのうち最初に追加したものの値を返す。
+    /// nameは大文字・小文字を区別しない。
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// 追加した順にヘッダーの名前と値の組を返す
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (&name[..], &value[..]))
+    }
+
+    pub fn set_body(&mut self, body: impl Into<Vec<u8>>) {
+        self.body = ResponseBody::Fixed(body.into());
+    }
+
+    /// 長さが分からないボディを後から書き込む
+    pub fn set_streaming(&mut self) {
+        self.body = ResponseBody::Streaming;
+    }
+
+    /// ストリーミングするボディの終わりの示し方を返す。
+    /// ボディが決まっているか, ボディを持てない場合はNoneを返す。
+    /// chunkedを理解しないHTTP/1.0のクライアントには接続を閉じて終わりを示す。
+    pub fn transfer_mode(&self, version: HttpVersion) -> Option<TransferMode> {
+        match self.body {
+            ResponseBody::Streaming if self.allows_body() => {
+                if version >= HttpVersion::HTTP_1_1 {
+                    Some(TransferMode::Chunked)
+                } else {
+                    Some(TransferMode::CloseDelimited)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// ステータスコードから, レスポンスがボディを持てるかどうかを返す。
+    /// 1xx, 204, 304はボディを持たないのでContent-Lengthを追加しない。
+    ///
+    /// References:
+    /// https://www.rfc-editor.org/rfc/rfc9112#name-message-body-length
+    pub fn allows_body(&self) -> bool {
+        !self.status.is_informational()
+            && self.status != StatusCode::NO_CONTENT
+            && self.status != StatusCode::NOT_MODIFIED
+    }
+
+    /// ステータスラインとヘッダーセクションをoutに書き込む。
+    /// 設定されていなければDate, Serverを追加する。
+    /// 長さが決まっているボディにはContent-Lengthを, ストリーミングするボディには
+    /// Transfer-Encoding: chunkedを追加する。
+    /// ボディと食い違うとレスポンスの区切りを誤らせるので, 設定されたContent-Lengthと
+    /// Transfer-Encodingは送信しない。
+    /// Connectionはcontextの判断に合わせるため, 設定されていても置き換える。
+    /// 接続を閉じて終わりを示すボディの場合は, context.keep_aliveに関わらずcloseを送る。
+    ///
+    /// HEADへのレスポンスでは, GETの場合と同じContent-Lengthを送信する。
+    ///
+    /// References:
+    /// https://www.rfc-editor.org/rfc/rfc9110#name-head
+    pub fn serialize_head(&self, out: &mut Vec<u8>, context: &ResponseContext) {
+        out.extend_from_slice(b"HTTP/1.1 ");
+        out.extend_from_slice(self.status.to_string().as_bytes());
+        out.push(b' ');
+        // reason-phraseに改行が含まれるとレスポンスを分割できてしまうので除く
+        out.extend(self.reason.bytes().filter(|&c| c != b'\r' && c != b'\n'));
+        out.extend_from_slice(b"\r\n");
+
+        // 1xxは途中経過なので, 最終的なレスポンスにだけ追加する
+        let is_final = !self.status.is_informational();
+        if let Some(date) = context
+            .date
+            .filter(|_| is_final && self.header("Date").is_none())
+        {
+            write_header(out, "Date", date);
+        }
+        if let Some(server) = context
+            .server
+            .filter(|_| is_final && self.header("Server").is_none())
+        {
+            write_header(out, "Server", server.as_bytes());
+        }
+        for (name, value) in &self.headers {
+            if ["Connection", "Content-Length", "Transfer-Encoding"]
+                .iter()
+                .any(|reserved| name.eq_ignore_ascii_case(reserved))
+            {
+                continue;
+            }
+            write_header(out, name, value);
+        }
+        let mode = self.body_mode(context);
+        match &self.body {
+            ResponseBody::Fixed(body) if self.allows_body() => {
+                write_header(out, "Content-Length", body.len().to_string().as_bytes());
+            }
+            ResponseBody::Streaming if mode == Some(TransferMode::Chunked) => {
+                write_header(out, "Transfer-Encoding", b"chunked");
+            }
+            _ => {}
+        }
+        if is_final {
+            // HTTP/1.1以降は接続を維持するのがデフォルトなので, 閉じる場合だけ示す
+            if !context.keep_alive || mode == Some(TransferMode::CloseDelimited) {
+                write_header(out, "Connection", b"close");
+            } else if !context.version.keep_alive_by_default() {
+                write_header(out, "Connection", b"keep-alive");
+            }
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+
+    /// レスポンスをoutに書き込む。
+    /// ストリーミングするボディの場合はヘッダーセクションまでを書き込み, 続きを書き込むBodyWriterを返す。
+    /// HEADへのレスポンスではボディを書き込まず, BodyWriterも返さない。
+    pub fn serialize(&self, out: &mut Vec<u8>, context: &ResponseContext) -> Option<BodyWriter> {
+        self.serialize_head(out, context);
+        match &self.body {
+            ResponseBody::Fixed(body) if self.allows_body() && !context.head => {
+                out.extend_from_slice(body)
+            }
+            _ => {}
+        }
+        self.body_mode(context).map(BodyWriter::new)
+    }
+
+    /// HEADへのレスポンスはボディを送信しないので, ストリーミングするボディの終わりも示さない
+    fn body_mode(&self, context: &ResponseContext) -> Option<TransferMode> {
+        if context.head {
+            return None;
+        }
+        self.transfer_mode(context.version)
+    }
+}
+
+/// ストリーミングするボディを書き込む
+///
+/// chunked-body = *chunk last-chunk trailer-section CRLF
+/// chunk = chunk-size [ chunk-ext ] CRLF chunk-data CRLF
+/// last-chunk = 1*("0") [ chunk-ext ] CRLF
+#[derive(Clone, Debug)]
+pub struct BodyWriter {
+    mode: TransferMode,
+    finished: bool,
+}
+
+impl BodyWriter {
+    pub fn new(mode: TransferMode) -> Self {
+        BodyWriter {
+            mode,
+            finished: false,
+        }
+    }
+
+    pub fn mode(&self) -> TransferMode {
+        self.mode
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// ボディの一部をoutに書き込む。chunkedの場合は1つのchunkになる。
+    pub fn write(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        // 長さ0のchunkはlast-chunkと区別できないので送らない
+        if self.finished || data.is_empty() {
+            return;
+        }
+        match self.mode {
+            TransferMode::Chunked => {
+                out.extend_from_slice(format!("{:X}\r\n", data.len()).as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
+            }
+            TransferMode::CloseDelimited => out.extend_from_slice(data),
+        }
+    }
+
+    /// ボディの終わりをoutに書き込む。chunkedの場合はlast-chunkの後にtrailersを送信する。
+    /// 接続を閉じて終わりを示す場合はtrailersを送れないので捨てる。
+    /// trailersに不正なfieldが含まれる場合は何も書き込まずにエラーを返す。
+    ///
+    /// References:
+    /// https://www.rfc-editor.org/rfc/rfc9112#name-chunked-trailer-section
+    pub fn finish(
+        &mut self,
+        trailers: &[(&str, &[u8])],
+        out: &mut Vec<u8>,
+    ) -> Result<(), RashinErr> {
+        if self.finished {
+            return Ok(());
+        }
+        if !trailers
+            .iter()
+            .all(|(name, value)| is_valid_field(name, value))
+        {
+            return Err(RashinErr::InvalidHeader("trailer"));
+        }
+        self.finished = true;
+        if self.mode == TransferMode::Chunked {
+            out.extend_from_slice(b"0\r\n");
+            for (name, value) in trailers {
+                write_header(out, name, value);
+            }
+            out.extend_from_slice(b"\r\n");
+        }
+        Ok(())
+    }
+}
+
+/// field-nameがtokenで, field-valueに改行などを含まないかどうか
+fn is_valid_field(name: &str, value: &[u8]) -> bool {
+    !name.is_empty() && all_tchar(name.as_bytes()) && all_field_content(value)
+}
+
+fn write_header(out: &mut Vec<u8>, name: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(b": ");
+    out.extend_from_slice(value);
+    out.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATE: &[u8] = b"Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn context(keep_alive: bool) -> ResponseContext<'static> {
+        ResponseContext {
+            date: None,
+            server: None,
+            version: HttpVersion::HTTP_1_1,
+            keep_alive,
+            head: false,
+        }
+    }
+
+    #[test]
+    fn serialize_response() {
+        let mut response = Response::new(StatusCode::OK);
+        response.add_header("Content-Type", "text/plain").unwrap();
+        response.add_header("X-Tag", "a").unwrap();
+        response.add_header("x-tag", "b").unwrap();
+        response.set_body("hello");
+
+        let mut out = Vec::new();
+        response.serialize(&mut out, &context(true));
+        assert_eq!(
+            out,
+            b"HTTP/1.1 200 OK\r\n\
+Content-Type: text/plain\r\n\
+X-Tag: a\r\n\
+x-tag: b\r\n\
+Content-Length: 5\r\n\
+\r\n\
+hello"
+        );
+        assert_eq!(response.header("X-TAG"), Some(&b"a"[..]));
+        assert_eq!(response.headers().count(), 3);
+    }
+
+    #[test]
+    fn serialize_status_line_only() {
+        let mut out = Vec::new();
+        Response::new(StatusCode::NO_CONTENT).serialize(&mut out, &context(true));
+        assert_eq!(out, b"HTTP/1.1 204 No Content\r\n\r\n");
+
+        // 標準のreason-phraseが無い場合でもSPは省略しない
+        let mut out = Vec::new();
+        Response::new(StatusCode::new(299).unwrap()).serialize(&mut out, &context(true));
+        assert_eq!(out, b"HTTP/1.1 299 \r\nContent-Length: 0\r\n\r\n");
+
+        let mut response = Response::new(StatusCode::OK);
+        response.reason = Cow::Borrowed("Fine\r\nX-Injected: 1");
+        let mut out = Vec::new();
+        response.serialize(&mut out, &context(true));
+        assert_eq!(
+            out,
+            b"HTTP/1.1 200 FineX-Injected: 1\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn invalid_header_should_be_rejected() {
+        let mut response = Response::new(StatusCode::OK);
+        assert!(response.add_header("", "a").is_err());
+        assert!(response.add_header("X Tag", "a").is_err());
+        assert!(response.add_header("X-Tag", "a\r\nX-Injected: 1").is_err());
+        assert!(response.add_header("X-Tag", "caf\u{e9}").is_ok());
+        assert_eq!(response.headers().count(), 1);
+    }
+
+    #[test]
+    fn automatic_headers() {
+        let mut response = Response::new(StatusCode::NOT_FOUND);
+        response.set_body("missing");
+        let context = ResponseContext {
+            date: Some(DATE),
+            server: Some("rashin"),
+            version: HttpVersion::HTTP_1_1,
+            keep_alive: false,
+            head: false,
+        };
+        let mut out = Vec::new();
+        response.serialize(&mut out, &context);
+        assert_eq!(
+            out,
+            b"HTTP/1.1 404 Not Found\r\n\
+Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+Server: rashin\r\n\
+Content-Length: 7\r\n\
+Connection: close\r\n\
+\r\n\
+missing"
+        );
+    }
+
+    #[test]
+    fn headers_set_by_handler_take_precedence() {
+        let mut response = Response::new(StatusCode::OK);
+        response.add_header("Server", "custom").unwrap();
+        response.add_header("Content-Length", "3").unwrap();
+        response.add_header("Connection", "close").unwrap();
+        response.set_body("abc");
+        let context = ResponseContext {
+            date: Some(DATE),
+            server: Some("rashin"),
+            version: HttpVersion::HTTP_1_0,
+            keep_alive: true,
+            head: false,
+        };
+        let mut out = Vec::new();
+        response.serialize(&mut out, &context);
+        // Connectionは接続の状態に合わせる
+        assert_eq!(
+            out,
+            b"HTTP/1.1 200 OK\r\n\
+Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+Server: custom\r\n\
+Content-Length: 3\r\n\
+Connection: keep-alive\r\n\
+\r\n\
+abc"
+        );
+    }
+
+    #[test]
+    fn framing_headers_set_by_handler_are_dropped() {
+        let mut response = Response::new(StatusCode::OK);
+        response.add_header("Content-Length", "10").unwrap();
+        response.add_header("Transfer-Encoding", "gzip").unwrap();
+        response.set_body("abc");
+        let mut out = Vec::new();
+        response.serialize(&mut out, &context(true));
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc");
+
+        response.set_streaming();
+        let mut out = Vec::new();
+        response.serialize(&mut out, &context(true));
+        assert_eq!(
+            out,
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn head_response_has_no_body() {
+        let context = ResponseContext {
+            head: true,
+            ..context(true)
+        };
+        let mut response = Response::new(StatusCode::OK);
+        response.set_body("hello");
+        let mut out = Vec::new();
+        assert!(response.serialize(&mut out, &context).is_none());
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n");
+
+        response.set_streaming();
+        let mut out = Vec::new();
+        assert!(response.serialize(&mut out, &context).is_none());
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\n\r\n");
+    }
+
+    #[test]
+    fn response_without_body() {
+        let context = ResponseContext {
+            date: Some(DATE),
+            server: Some("rashin"),
+            version: HttpVersion::HTTP_1_1,
+            keep_alive: true,
+            head: false,
+        };
+        let mut out = Vec::new();
+        Response::new(StatusCode::CONTINUE).serialize(&mut out, &context);
+        assert_eq!(out, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        let mut response = Response::new(StatusCode::NO_CONTENT);
+        response.set_body("ignored");
+        let mut out = Vec::new();
+        response.serialize(&mut out, &context);
+        assert_eq!(
+            out,
+            b"HTTP/1.1 204 No Content\r\n\
+Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+Server: rashin\r\n\
+\r\n"
+        );
+    }
+
+    #[test]
+    fn streaming_body_is_chunked_for_http11() {
+        let mut response = Response::new(StatusCode::OK);
+        response.add_header("Trailer", "X-Checksum").unwrap();
+        response.set_streaming();
+        let mut out = Vec::new();
+        let mut writer = response.serialize(&mut out, &context(true)).unwrap();
+        assert_eq!(writer.mode(), TransferMode::Chunked);
+        writer.write(b"hello, ", &mut out);
+        writer.write(b"", &mut out);
+        writer.write(&[b'a'; 26], &mut out);
+        writer.finish(&[("X-Checksum", b"1234")], &mut out).unwrap();
+        assert!(writer.is_finished());
+        writer.write(b"ignored", &mut out);
+
+        let mut expected = b"HTTP/1.1 200 OK\r\n\
+Trailer: X-Checksum\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+7\r\nhello, \r\n\
+1A\r\n"
+            .to_vec();
+        expected.extend_from_slice(&[b'a'; 26]);
+        expected.extend_from_slice(b"\r\n0\r\nX-Checksum: 1234\r\n\r\n");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn streaming_body_is_close_delimited_for_http10() {
+        let mut response = Response::new(StatusCode::OK);
+        response.set_streaming();
+        let context = ResponseContext {
+            date: None,
+            server: None,
+            version: HttpVersion::HTTP_1_0,
+            keep_alive: true,
+            head: false,
+        };
+        let mut out = Vec::new();
+        let mut writer = response.serialize(&mut out, &context).unwrap();
+        assert_eq!(writer.mode(), TransferMode::CloseDelimited);
+        writer.write(b"hello", &mut out);
+        writer.finish(&[("X-Checksum", b"1234")], &mut out).unwrap();
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nhello");
+    }
+
+    #[test]
+    fn streaming_without_body() {
+        let mut response = Response::new(StatusCode::NO_CONTENT);
+        response.set_streaming();
+        let mut out = Vec::new();
+        assert!(response.serialize(&mut out, &context(true)).is_none());
+        assert_eq!(out, b"HTTP/1.1 204 No Content\r\n\r\n");
+
+        let mut writer = BodyWriter::new(TransferMode::Chunked);
+        let mut out = Vec::new();
+        assert!(writer.finish(&[("X Bad", b"1")], &mut out).is_err());
+        assert!(writer.finish(&[("X-Sum", b"1\r\n")], &mut out).is_err());
+        assert!(out.is_empty());
+        writer.finish(&[], &mut out).unwrap();
+        assert_eq!(out, b"0\r\n\r\n");
+    }
+}
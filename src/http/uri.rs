@@ -0,0 +1,459 @@
+//! URIの構成要素のデコード
+//!
+//! request-targetのパース時にpercent-encodingが正しいことを検証しているので、
+//! ここでのデコードは失敗しない。デコードが不要な場合はバッファを借用したまま返す。
+//!
+//! References:
+//! https://www.rfc-editor.org/rfc/rfc3986#section-2.1
+use std::borrow::Cow;
+use std::str::Utf8Error;
+
+use super::parse_utility::hex_value;
+
+/// "%"の後に2桁のHEXDIGが続いているかを確認する。
+///
+/// pct-encoded = "%" HEXDIG HEXDIG
+pub fn is_valid_percent_encoding(input: &[u8]) -> bool {
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' {
+            match (input.get(i + 1), input.get(i + 2)) {
+                (Some(&h), Some(&l)) if hex_value(h).is_some() && hex_value(l).is_some() => {
+                    i += 3;
+                    continue;
+                }
+                _ => return false,
+            }
+        }
+        i += 1;
+    }
+    true
+}
+
+/// pct-encodedをデコードする。
+/// 不正なエスケープはそのまま残す。
+pub fn percent_decode(input: &[u8]) -> Cow<'_, [u8]> {
+    decode(input, false)
+}
+
+fn decode(input: &[u8], plus_as_space: bool) -> Cow<'_, [u8]> {
+    let needs_decode = input
+        .iter()
+        .any(|&c| c == b'%' || (plus_as_space && c == b'+'));
+    if !needs_decode {
+        return Cow::Borrowed(input);
+    }
+
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'%' => {
+                let high = input.get(i + 1).and_then(|&c| hex_value(c));
+                let low = input.get(i + 2).and_then(|&c| hex_value(c));
+                if let (Some(high), Some(low)) = (high, low) {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                decoded.push(b'%');
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            c => decoded.push(c),
+        }
+        i += 1;
+    }
+    Cow::Owned(decoded)
+}
+
+/// pathを正規化する際の検証の厳しさ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathMode {
+    /// エンコードされた"/"やNULもデコードして受け付ける
+    Lenient,
+    /// エンコードされた"/"(%2F)とNUL(%00)を含むpathを拒否する
+    Strict,
+}
+
+/// pathをデコードしてから正規化する。
+/// デコード後に正規化するので"%2e%2e"のようにエンコードされたdot-segmentも取り除かれる。
+/// Strictの場合にエンコードされた"/"やNULが含まれていればNoneを返す。
+pub fn normalize_path(path: &[u8], mode: PathMode) -> Option<Cow<'_, [u8]>> {
+    if mode == PathMode::Strict && contains_forbidden_escape(path) {
+        return None;
+    }
+    match percent_decode(path) {
+        Cow::Borrowed(decoded) => Some(remove_dot_segments(decoded)),
+        Cow::Owned(decoded) => Some(Cow::Owned(remove_dot_segments(&decoded).into_owned())),
+    }
+}
+
+fn contains_forbidden_escape(path: &[u8]) -> bool {
+    path.windows(3)
+        .any(|w| w[0] == b'%' && (w[1..].eq_ignore_ascii_case(b"2f") || w[1..].eq(b"00")))
+}
+
+/// dot-segmentを取り除き、連続する"/"を1つにまとめる。
+/// ".."でルートより上に遡ることはできないので、ドキュメントルートの外を指すことはない。
+/// 最後のセグメントが"."または".."の場合は末尾の"/"を残す。
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc3986#section-5.2.4
+pub fn remove_dot_segments(path: &[u8]) -> Cow<'_, [u8]> {
+    if !needs_normalization(path) {
+        return Cow::Borrowed(path);
+    }
+
+    let mut segments: Vec<&[u8]> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path.split(|&c| c == b'/') {
+        match segment {
+            b"" | b"." => {
+                trailing_slash = true;
+            }
+            b".." => {
+                segments.pop();
+                trailing_slash = true;
+            }
+            _ => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut normalized = Vec::with_capacity(path.len());
+    for segment in &segments {
+        normalized.push(b'/');
+        normalized.extend_from_slice(segment);
+    }
+    if trailing_slash || segments.is_empty() {
+        normalized.push(b'/');
+    }
+    Cow::Owned(normalized)
+}
+
+fn needs_normalization(path: &[u8]) -> bool {
+    if path.first() != Some(&b'/') {
+        return true;
+    }
+    path.windows(2).any(|w| w == b"//")
+        || path.windows(3).any(|w| w == b"/./")
+        || path.windows(4).any(|w| w == b"/../")
+        || path.ends_with(b"/.")
+        || path.ends_with(b"/..")
+}
+
+/// application/x-www-form-urlencoded形式のqueryを名前と値の組に分解するイテレーター。
+/// 組は"&"で区切られ、名前と値は最初の"="で区切られる。空の組は読み飛ばす。
+///
+/// References:
+/// https://url.spec.whatwg.org/#urlencoded-parsing
+pub struct QueryPairs<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> QueryPairs<'a> {
+    pub fn new(query: &'a [u8]) -> Self {
+        QueryPairs { rest: query }
+    }
+}
+
+impl<'a> Iterator for QueryPairs<'a> {
+    type Item = (Cow<'a, [u8]>, Cow<'a, [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.rest.is_empty() {
+            let (pair, rest) = match self.rest.iter().position(|&c| c == b'&') {
+                Some(n) => (&self.rest[..n], &self.rest[n + 1..]),
+                None => (self.rest, &self.rest[self.rest.len()..]),
+            };
+            self.rest = rest;
+            if pair.is_empty() {
+                continue;
+            }
+            let (name, value) = match pair.iter().position(|&c| c == b'=') {
+                Some(n) => (&pair[..n], &pair[n + 1..]),
+                None => (pair, &pair[pair.len()..]),
+            };
+            return Some((decode(name, true), decode(value, true)));
+        }
+        None
+    }
+}
+
+/// uri-hostとportの位置。
+/// start..endがuri-hostで, IP-literalの場合は"[]"を含む。
+///
+/// uri-host = IP-literal / IPv4address / reg-name
+/// port = *DIGIT
+///
+/// References:
+/// https://www.rfc-editor.org/rfc/rfc3986#section-3.2.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Host {
+    pub start: usize,
+    pub end: usize,
+    pub port: Option<u16>,
+}
+
+impl Host {
+    /// offsetから始まるinputを`uri-host [ ":" port ]`として読み込む。
+    /// 構文が正しくない場合はNoneを返す。portが空の場合は省略されたものとして扱う。
+    pub fn parse(input: &[u8], offset: usize) -> Option<Host> {
+        let host_len = if input.first() == Some(&b'[') {
+            let close = input.iter().position(|&c| c == b']')?;
+            if !is_valid_ip_literal(&input[1..close]) {
+                return None;
+            }
+            close + 1
+        } else {
+            let len = input.iter().position(|&c| c == b':').unwrap_or(input.len());
+            if !is_valid_reg_name(&input[..len]) {
+                return None;
+            }
+            len
+        };
+
+        let port = match &input[host_len..] {
+            [] | [b':'] => None,
+            [b':', port @ ..] => {
+                if !port.iter().all(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+                Some(std::str::from_utf8(port).ok()?.parse::<u16>().ok()?)
+            }
+            _ => return None,
+        };
+        Some(Host {
+            start: offset,
+            end: offset + host_len,
+            port,
+        })
+    }
+
+    /// uri-hostを文字列として返す。
+    /// parseで確認した構文はASCIIだけからなるので, parseで得たHostであればUTF-8として解釈できる。
+    pub fn name<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Result<&'a str, Utf8Error> {
+        std::str::from_utf8(self.name_bytes(buffer))
+    }
+
+    pub fn name_bytes<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> &'a [u8] {
+        &buffer.as_ref()[self.start..self.end]
+    }
+
+    /// 仮想ホストの選択に使えるように正規化したホスト名を返す。
+    /// ホスト名は大文字・小文字を区別しないので小文字にし, 末尾の"."を取り除く。
+    pub fn normalized<'a, T: AsRef<[u8]> + ?Sized>(&self, buffer: &'a T) -> Cow<'a, [u8]> {
+        let name = self.name_bytes(buffer);
+        let name = name.strip_suffix(b".").unwrap_or(name);
+        if name.iter().any(|c| c.is_ascii_uppercase()) {
+            return Cow::Owned(name.to_ascii_lowercase());
+        }
+        Cow::Borrowed(name)
+    }
+}
+
+/// IPv4addressまたはreg-nameであることを確認する。
+/// 数字と"."だけからなる場合はIPv4addressとして解釈できなければならない。
+///
+/// reg-name = *( unreserved / pct-encoded / sub-delims )
+fn is_valid_reg_name(host: &[u8]) -> bool {
+    if !host.is_empty() && host.iter().all(|&c| c.is_ascii_digit() || c == b'.') {
+        return std::str::from_utf8(host)
+            .ok()
+            .and_then(|host| host.parse::<std::net::Ipv4Addr>().ok())
+            .is_some();
+    }
+    is_valid_percent_encoding(host)
+        && host
+            .iter()
+            .all(|&c| c == b'%' || is_unreserved(c) || is_sub_delim(c))
+}
+
+/// "[]"の内側がIPv6addressまたはIPvFutureであることを確認する。
+///
+/// IP-literal = "[" ( IPv6address / IPvFuture ) "]"
+/// IPvFuture = "v" 1*HEXDIG "." 1*( unreserved / sub-delims / ":" )
+fn is_valid_ip_literal(literal: &[u8]) -> bool {
+    if let Some(future) = literal
+        .strip_prefix(b"v")
+        .or_else(|| literal.strip_prefix(b"V"))
+    {
+        let dot = match future.iter().position(|&c| c == b'.') {
+            Some(n) => n,
+            None => return false,
+        };
+        let (version, rest) = (&future[..dot], &future[dot + 1..]);
+        return !version.is_empty()
+            && version.iter().all(|c| c.is_ascii_hexdigit())
+            && !rest.is_empty()
+            && rest
+                .iter()
+                .all(|&c| c == b':' || is_unreserved(c) || is_sub_delim(c));
+    }
+    std::str::from_utf8(literal)
+        .ok()
+        .and_then(|literal| literal.parse::<std::net::Ipv6Addr>().ok())
+        .is_some()
+}
+
+/// unreserved = ALPHA / DIGIT / "-" / "." / "_" / "~"
+fn is_unreserved(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'-' | b'.' | b'_' | b'~')
+}
+
+/// sub-delims = "!" / "$" / "&" / "'" / "(" / ")" / "*" / "+" / "," / ";" / "="
+fn is_sub_delim(c: u8) -> bool {
+    matches!(
+        c,
+        b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_percent_encoding() {
+        assert!(is_valid_percent_encoding(b"/a%20b"));
+        assert!(is_valid_percent_encoding(b"/%e3%81%82"));
+        assert!(!is_valid_percent_encoding(b"/a%2"));
+        assert!(!is_valid_percent_encoding(b"/a%zz"));
+        assert!(!is_valid_percent_encoding(b"%"));
+    }
+
+    #[test]
+    fn decode_without_escape_borrows_input() {
+        let decoded = percent_decode(b"/index.html");
+        assert!(matches!(decoded, Cow::Borrowed(_)));
+        assert_eq!(&decoded[..], b"/index.html");
+    }
+
+    #[test]
+    fn decode_escaped_path() {
+        assert_eq!(&percent_decode(b"/a%20b+c")[..], b"/a b+c");
+        assert_eq!(&percent_decode(b"/%E3%81%82")[..], "/あ".as_bytes());
+        assert_eq!(&percent_decode(b"/100%")[..], b"/100%");
+    }
+
+    #[test]
+    fn remove_dot_segments_examples() {
+        let cases: [(&[u8], &[u8]); 10] = [
+            (b"/", b"/"),
+            (b"/index.html", b"/index.html"),
+            (b"/a/b/c/./../../g", b"/a/g"),
+            (b"/a/b/..", b"/a/"),
+            (b"/a/.", b"/a/"),
+            (b"/../../etc/passwd", b"/etc/passwd"),
+            (b"/..", b"/"),
+            (b"//a///b//", b"/a/b/"),
+            (b"/a/..b/c", b"/a/..b/c"),
+            (b"/a/.../b", b"/a/.../b"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(&remove_dot_segments(path)[..], expected);
+        }
+    }
+
+    #[test]
+    fn normalized_path_without_change_borrows_input() {
+        let normalized = normalize_path(b"/a/b", PathMode::Strict).unwrap();
+        assert!(matches!(normalized, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn normalize_encoded_dot_segments() {
+        let normalized = normalize_path(b"/%2e%2e/%2E%2E/etc/passwd", PathMode::Strict).unwrap();
+        assert_eq!(&normalized[..], b"/etc/passwd");
+    }
+
+    #[test]
+    fn strict_mode_rejects_encoded_slash_and_nul() {
+        assert!(normalize_path(b"/a%2Fb", PathMode::Strict).is_none());
+        assert!(normalize_path(b"/a%2fb", PathMode::Strict).is_none());
+        assert!(normalize_path(b"/a%00.html", PathMode::Strict).is_none());
+        assert_eq!(
+            &normalize_path(b"/a%2F..%2Fb", PathMode::Lenient).unwrap()[..],
+            b"/b"
+        );
+        assert_eq!(
+            &normalize_path(b"/a%00", PathMode::Lenient).unwrap()[..],
+            b"/a\0"
+        );
+    }
+
+    #[test]
+    fn iterate_query_pairs() {
+        let pairs: Vec<_> = QueryPairs::new(b"q=rust+lang&empty=&flag&&x=%26%3D")
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (b"q".to_vec(), b"rust lang".to_vec()),
+                (b"empty".to_vec(), b"".to_vec()),
+                (b"flag".to_vec(), b"".to_vec()),
+                (b"x".to_vec(), b"&=".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_pairs_borrow_when_possible() {
+        let mut pairs = QueryPairs::new(b"a=1&b=%20");
+        let (name, value) = pairs.next().unwrap();
+        assert!(matches!(name, Cow::Borrowed(_)));
+        assert!(matches!(value, Cow::Borrowed(_)));
+        let (_, value) = pairs.next().unwrap();
+        assert!(matches!(value, Cow::Owned(_)));
+        assert!(pairs.next().is_none());
+    }
+
+    #[test]
+    fn parse_host_and_port() {
+        let host = Host::parse(b"Example.COM.:8080", 10).unwrap();
+        assert_eq!(host.start, 10);
+        assert_eq!(host.end, 10 + 12);
+        assert_eq!(host.port, Some(8080));
+        let buf = b"Example.COM.";
+        let host = Host::parse(buf, 0).unwrap();
+        assert_eq!(host.name(buf), Ok("Example.COM."));
+        assert_eq!(host.name_bytes(buf), b"Example.COM.");
+        assert_eq!(host.normalized(buf), &b"example.com"[..]);
+        assert_eq!(host.port, None);
+
+        let buf = b"[::1]:443";
+        let host = Host::parse(buf, 0).unwrap();
+        assert_eq!(host.name(buf), Ok("[::1]"));
+        assert_eq!(host.port, Some(443));
+        assert!(Host::parse(b"[v1.fe80::a+en1]", 0).is_some());
+        assert!(Host::parse(b"192.0.2.1:80", 0).is_some());
+        assert!(Host::parse(b"localhost:", 0).unwrap().port.is_none());
+        assert!(Host::parse(b"", 0).is_some());
+    }
+
+    #[test]
+    fn invalid_host_should_failed() {
+        let cases: [&[u8]; 10] = [
+            b"user@example.com",
+            b"example.com:80:80",
+            b"example.com:http",
+            b"example.com:65536",
+            b"256.0.0.1",
+            b"1.2.3",
+            b"[::1",
+            b"[example.com]",
+            b"[::1]x",
+            b"exa mple.com",
+        ];
+        for host in cases {
+            assert!(
+                Host::parse(host, 0).is_none(),
+                "{}",
+                String::from_utf8_lossy(host)
+            );
+        }
+    }
+}
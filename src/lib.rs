@@ -0,0 +1,5 @@
+pub mod core;
+pub mod error;
+pub mod http;
+pub mod syscall;
+pub mod system_utils;
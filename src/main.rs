@@ -1,19 +1,13 @@
-mod core;
-mod error;
-mod http;
-mod syscall;
-mod system_utils;
-
-use libc;
-
 use std::collections::HashMap;
 use std::mem;
 use std::os::fd;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
-use crate::core::{init_http_event, Connection, Event, EventState};
-use crate::error::RashinErr;
+use rashin::core::{init_http_event, Connection, Event, EventState, ServerConfig};
+use rashin::error::RashinErr;
+use rashin::{syscall, system_utils};
 
 // Read these document before develpment.
 // * Nginx Development Guide
@@ -28,7 +22,7 @@ const TIMEOUT_CLOCKS: i32 = 100;
 fn main() {
     let addr = libc::sockaddr_in {
         sin_family: libc::AF_INET as u16,
-        sin_port: (8080 as u16).to_be(), // htons(8080)
+        sin_port: 8080_u16.to_be(), // htons(8080)
         sin_addr: libc::in_addr {
             s_addr: libc::INADDR_ANY,
         },
@@ -36,6 +30,8 @@ fn main() {
     };
     let mut addr = unsafe { mem::transmute::<libc::sockaddr_in, libc::sockaddr>(addr) };
 
+    let config = Arc::new(ServerConfig::default());
+
     println!("Start Server!");
     let listener_fd = system_utils::create_listner_socket(&addr).unwrap();
 
@@ -81,8 +77,8 @@ fn main() {
             }
         };
 
-        for n in 0..events_num {
-            let event_fd = events_buffer[n].u64 as fd::RawFd;
+        for fired_event in events_buffer.iter().take(events_num) {
+            let event_fd = fired_event.u64 as fd::RawFd;
 
             // Accept incoming connection requests.
             if event_fd == listener_fd {
@@ -119,7 +115,7 @@ fn main() {
                     .unwrap();
 
                     syscall::fnctl(accept_fd).unwrap();
-                    let connection = Connection::new(accept_fd);
+                    let connection = Connection::new(accept_fd, Arc::clone(&config));
                     let event = init_http_event(connection);
                     event_map.insert(accept_fd, event);
                 }
@@ -127,13 +123,11 @@ fn main() {
             }
 
             // Pop event from the event_map
-            let flags = events_buffer[n].events as i32;
-            println!("Event fired: FD: {}, Flag: {}", event_fd, flags as i32);
+            let flags = fired_event.events as i32;
+            println!("Event fired: FD: {}, Flag: {}", event_fd, flags);
 
-            // 今のままだとBufferなどもコピーされるのであんまりよくない
-            let event_option = event_map.get(&event_fd).cloned();
-
-            if let Some(mut event) = event_option {
+            // パースの途中経過を次のイベントに引き継ぐため, event_mapのEventを直接更新する
+            if let Some(event) = event_map.get_mut(&event_fd) {
                 let is_readable = (flags & libc::EPOLLIN) > 0;
 
                 if is_readable & event.is_ready() {
@@ -145,12 +139,10 @@ fn main() {
                 if is_writable & event.is_ready() {
                     event.writable = true;
                 }
-                (event.handler)(event_fd, &mut event);
+                (event.handler)(event_fd, event);
                 if let EventState::Shutdown = event.state {
-                    log::debug!("Shutdown {}", event_fd);
-                    syscall::shutdown(event_fd).unwrap();
-                    syscall::epoll_ctl(epoll_fd, libc::EPOLL_CTL_DEL, event_fd, None).unwrap();
-                    syscall::close(event_fd).unwrap();
+                    event_map.remove(&event_fd);
+                    close_connection(epoll_fd, event_fd);
                 }
             } else {
                 // Something wrong
@@ -158,6 +150,24 @@ fn main() {
                 syscall::epoll_ctl(epoll_fd, libc::EPOLL_CTL_DEL, event_fd, None).unwrap();
             }
         }
+
+        // 次のリクエストを待つ時間や, リクエストを受信し終えるまでの時間が過ぎた接続を閉じる
+        let now = Instant::now();
+        let expired: Vec<fd::RawFd> = event_map
+            .iter()
+            .filter(|(_, event)| {
+                event
+                    .connection
+                    .as_ref()
+                    .is_some_and(|connection| connection.is_expired(now))
+            })
+            .map(|(&event_fd, _)| event_fd)
+            .collect();
+        for event_fd in expired {
+            log::debug!("Timeout {}", event_fd);
+            event_map.remove(&event_fd);
+            close_connection(epoll_fd, event_fd);
+        }
     }
 
     // Close
@@ -166,3 +176,14 @@ fn main() {
     syscall::close(listener_fd).unwrap();
     println!("End Server!");
 }
+
+/// 接続を監視対象から外して閉じる
+fn close_connection(epoll_fd: fd::RawFd, event_fd: fd::RawFd) {
+    log::debug!("Shutdown {}", event_fd);
+    // 相手が先に接続を閉じている場合はshutdownが失敗するが, closeはできる
+    if let Err(e) = syscall::shutdown(event_fd) {
+        log::debug!("Shutdown {}: {}", event_fd, e);
+    }
+    syscall::epoll_ctl(epoll_fd, libc::EPOLL_CTL_DEL, event_fd, None).unwrap();
+    syscall::close(event_fd).unwrap();
+}
@@ -1,11 +1,10 @@
+//! syscall.rs
+//! libcをsafeに使うためのユーティリティ関数.
+//! 原則としてシステムコールに対応した名称の関数を定義する.
 use crate::error::RashinErr;
 use std::mem;
 use std::os::fd::{self, AsRawFd};
 
-/// syscall.rs
-/// libcをsafeに使うためのユーティリティ関数.
-/// 原則としてシステムコールに対応した名称の関数を定義する.
-
 pub fn socket() -> Result<std::os::fd::RawFd, RashinErr> {
     let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
     if fd == -1 {
@@ -34,13 +33,9 @@ pub fn listen(fd: fd::RawFd, backlog: i32) -> Result<(), RashinErr> {
     Ok(())
 }
 
-pub fn setsockopt(
-    fd: fd::RawFd,
-    level: i32,
-    name: i32,
-    optval: *const libc::c_void,
-    optlen: u32,
-) -> Result<(), RashinErr> {
+pub fn setsockopt(fd: fd::RawFd, level: i32, name: i32, optval: &i32) -> Result<(), RashinErr> {
+    let optlen = mem::size_of::<i32>() as u32;
+    let optval = optval as *const i32 as *const libc::c_void;
     let error_code = unsafe { libc::setsockopt(fd.as_raw_fd(), level, name, optval, optlen) };
     if error_code == -1 {
         println!("`setsockopt` fails with errno {}.", errno());
@@ -93,6 +88,8 @@ pub fn accept(fd: i32, addr: &mut libc::sockaddr) -> Result<i32, RashinErr> {
 /// manpageによるとlibc::shutdownの引数は以下の3種類を利用することができる.
 /// * SHUT_RD: 読み込みを禁止する
 /// * SHUT_WR: 書き込みを禁止する
+/// * SHUT_RDWR: 読み込みと書き込みを禁止する
+///
 /// 参考1. Rust本体のTcpListener周りの関連実装
 /// https://github.com/rust-lang/rust/blob/11467b1c2a56bd2fd8272a7413190c814cfcba1f/library/std/src/sys/unix/net.rs#L379
 ///
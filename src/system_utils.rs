@@ -1,7 +1,5 @@
+//! 複数のシステムコールを組み合わせた, システム操作に関するユーティリティ
 use crate::{error::RashinErr, syscall};
-/// 複数のシステムコールを組み合わせた, システム操作に関するユーティリティ
-use libc;
-use std::mem;
 use std::os::fd;
 
 /// Listenerソケットを作成し, 引数で与えられたアドレスにバインドする.
@@ -13,26 +11,16 @@ pub fn create_listner_socket(addr: &libc::sockaddr) -> Result<fd::RawFd, RashinE
     // REUSEADDRを指定することで, ソケットを閉じた後にもTIME_WAIT状態にならずにすむ
     // TIME_WAIT状態だと一定時間ポートの再利用ができず, サーバーの再起動ができない
     let optval = 1;
-    if let Err(e) = syscall::setsockopt(
-        listener_fd,
-        libc::SOL_SOCKET,
-        libc::SO_REUSEADDR,
-        &optval as *const _ as *const libc::c_void,
-        mem::size_of::<i32>() as u32,
-    ) {
+    if let Err(e) = syscall::setsockopt(listener_fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, &optval)
+    {
         syscall::close(listener_fd).unwrap();
         return Err(e);
     }
 
     // Option: SO_REUSEPORTを指定する
     let optval = 1;
-    if let Err(e) = syscall::setsockopt(
-        listener_fd,
-        libc::SOL_SOCKET,
-        libc::SO_REUSEPORT,
-        &optval as *const _ as *const libc::c_void,
-        mem::size_of::<i32>() as u32,
-    ) {
+    if let Err(e) = syscall::setsockopt(listener_fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, &optval)
+    {
         syscall::close(listener_fd).unwrap();
         return Err(e);
     }